@@ -1,9 +1,60 @@
 use advent_of_code_2021::util::lines;
+use std::cmp::Ordering;
+use std::str::FromStr;
 
-fn main() {
-    let lines = lines();
+/// What to do when a column has exactly as many ones as zeros: which bit
+/// counts as the most common one, the other being the least common.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Tie {
+    One,
+    Zero,
+    Error,
+}
+
+impl FromStr for Tie {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "one" => Ok(Tie::One),
+            "zero" => Ok(Tie::Zero),
+            "error" => Ok(Tie::Error),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Criteria {
+    Most,
+    Least,
+}
+
+/// A tie was found at the given bit position while the policy was `Tie::Error`.
+#[derive(Debug, Eq, PartialEq)]
+struct Tied {
+    bit: usize,
+}
+
+fn pick(criteria: Criteria, ones: usize, zeros: usize, tie: Tie, bit: usize) -> Result<u8, Tied> {
+    let most = match (ones.cmp(&zeros), tie) {
+        (Ordering::Greater, _) | (Ordering::Equal, Tie::One) => b'1',
+        (Ordering::Less, _) | (Ordering::Equal, Tie::Zero) => b'0',
+        (Ordering::Equal, Tie::Error) => return Err(Tied { bit }),
+    };
+    match criteria {
+        Criteria::Most => Ok(most),
+        Criteria::Least => Ok(if most == b'1' { b'0' } else { b'1' }),
+    }
+}
+
+fn power(lines: &[String], tie: Tie) -> Result<u64, Tied> {
     let count = lines.len();
-    let bits = lines.iter().map(|line| line.len()).max().unwrap();
+    let bits = lines
+        .iter()
+        .map(|line| line.len())
+        .max()
+        .unwrap_or_default();
 
     let mut ones = vec![0usize; bits];
     for line in lines.iter() {
@@ -14,60 +65,149 @@ fn main() {
         }
     }
 
-    let gamma = ones
-        .iter()
-        .map(|n| if *n > count / 2 { 1 } else { 0 })
-        .fold(0u64, |acc, x| (acc << 1) | x);
-    let epsilon = ones
-        .iter()
-        .map(|n| if *n < count / 2 { 1 } else { 0 })
-        .fold(0u64, |acc, x| (acc << 1) | x);
-    println!("{}", gamma * epsilon);
-
-    let oxygen = search(
-        &lines,
-        |ones, zeros| if ones >= zeros { b'1' } else { b'0' },
-    );
-    let carbon = search(
-        &lines,
-        |ones, zeros| if ones >= zeros { b'0' } else { b'1' },
-    );
-    println!("{}", oxygen * carbon);
+    let mut gamma = 0u64;
+    let mut epsilon = 0u64;
+    for (i, n) in ones.iter().enumerate() {
+        let bit = pick(Criteria::Most, *n, count - *n, tie, i)?;
+        let x = if bit == b'1' { 1 } else { 0 };
+        gamma = (gamma << 1) | x;
+        epsilon = (epsilon << 1) | (1 - x);
+    }
+    Ok(gamma * epsilon)
+}
+
+fn life_support(lines: &[String], tie: Tie) -> Result<u64, Tied> {
+    let oxygen = search(lines, Criteria::Most, tie)?;
+    let carbon = search(lines, Criteria::Least, tie)?;
+    Ok(oxygen * carbon)
+}
+
+/// Usage: day03 [one|zero|error] < input
+///
+/// The tie policy defaults to `one`, which keeps lines with a 1 for oxygen
+/// and a 0 for CO2.
+fn main() {
+    let tie = match std::env::args().nth(1).map(|arg| arg.parse()) {
+        None => Tie::One,
+        Some(Ok(tie)) => tie,
+        Some(Err(())) => {
+            eprintln!("usage: day03 [one|zero|error]");
+            std::process::exit(2);
+        }
+    };
+
+    let lines = lines();
+    let result = power(&lines, tie).and_then(|power| Ok((power, life_support(&lines, tie)?)));
+    match result {
+        Ok((power, life_support)) => {
+            println!("{}", power);
+            println!("{}", life_support);
+        }
+        Err(Tied { bit }) => {
+            eprintln!("bit {} has as many ones as zeros", bit);
+            std::process::exit(1);
+        }
+    }
 }
 
-fn search<F>(lines: &[String], f: F) -> u64
-where
-    F: Fn(usize, usize) -> u8 + Copy,
-{
+fn search(lines: &[String], criteria: Criteria, tie: Tie) -> Result<u64, Tied> {
     let mut all = lines.to_vec();
     let mut prefix: usize = 0;
     while all.len() > 1 {
-        let remaining = filter(&all, prefix, f);
+        let remaining = filter(&all, prefix, criteria, tie)?;
         all = remaining;
         prefix += 1;
     }
     assert_eq!(all.len(), 1);
-    u64::from_str_radix(&all[0], 2).unwrap()
+    Ok(u64::from_str_radix(&all[0], 2).unwrap())
 }
 
-fn filter<F>(lines: &[String], prefix: usize, f: F) -> Vec<String>
-where
-    F: Fn(usize, usize) -> u8,
-{
+fn filter(
+    lines: &[String],
+    prefix: usize,
+    criteria: Criteria,
+    tie: Tie,
+) -> Result<Vec<String>, Tied> {
     let mut ones = 0usize;
     for line in lines {
-        let bit = line.as_bytes().iter().nth(prefix).unwrap();
-        if *bit == b'1' {
+        let bit = line.as_bytes()[prefix];
+        if bit == b'1' {
             ones += 1;
         }
     }
 
     let zeros = lines.len() - ones;
-    let target = f(ones, zeros);
+    let target = pick(criteria, ones, zeros, tie, prefix)?;
 
-    lines
+    Ok(lines
         .iter()
-        .filter(|line| line.as_bytes().iter().nth(prefix).unwrap() == &target)
+        .filter(|line| line.as_bytes()[prefix] == target)
         .cloned()
-        .collect()
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|line| line.to_string()).collect()
+    }
+
+    const EXAMPLE: &str = "00100 11110 10110 10111 10101 01111 00111 11100 10000 11001 00010 01010";
+
+    #[test]
+    fn test_power_even() {
+        let lines = input(EXAMPLE);
+        assert_eq!(lines.len(), 12);
+        assert_eq!(power(&lines, Tie::One), Ok(22 * 9));
+        assert_eq!(power(&lines, Tie::Error), Ok(22 * 9));
+    }
+
+    #[test]
+    fn test_power_odd() {
+        // 2 of 3 lines have a one in the first column: count / 2 == 1 used to
+        // mark the column as neither most nor least common.
+        let lines = input("110 101 011");
+        assert_eq!(power(&lines, Tie::Error), Ok(0)); // 0b111 * 0b000
+
+        let lines = input("100 011 010");
+        assert_eq!(power(&lines, Tie::Error), Ok(10)); // 0b010 * 0b101
+    }
+
+    #[test]
+    fn test_power_tie() {
+        let lines = input("10 01 11 00");
+        assert_eq!(power(&lines, Tie::One), Ok(0)); // 0b11 * 0b00
+        assert_eq!(power(&lines, Tie::Zero), Ok(0)); // 0b00 * 0b11
+        assert_eq!(power(&lines, Tie::Error), Err(Tied { bit: 0 }));
+
+        let lines = input("10 11");
+        assert_eq!(power(&lines, Tie::Zero), Ok(2)); // 0b10 * 0b01
+        assert_eq!(power(&lines, Tie::Error), Err(Tied { bit: 1 }));
+    }
+
+    #[test]
+    fn test_life_support() {
+        let lines = input(EXAMPLE);
+        assert_eq!(search(&lines, Criteria::Most, Tie::One), Ok(23));
+        assert_eq!(search(&lines, Criteria::Least, Tie::One), Ok(10));
+        assert_eq!(life_support(&lines, Tie::One), Ok(230));
+        assert_eq!(
+            search(&lines, Criteria::Most, Tie::Error),
+            Err(Tied { bit: 4 })
+        );
+        assert_eq!(life_support(&lines, Tie::Error), Err(Tied { bit: 4 }));
+
+        // With ties going to 0, oxygen keeps the 0 and CO2 the 1.
+        let lines = input("10 01");
+        assert_eq!(search(&lines, Criteria::Most, Tie::One), Ok(0b10));
+        assert_eq!(search(&lines, Criteria::Least, Tie::One), Ok(0b01));
+        assert_eq!(search(&lines, Criteria::Most, Tie::Zero), Ok(0b01));
+        assert_eq!(search(&lines, Criteria::Least, Tie::Zero), Ok(0b10));
+
+        let lines = input("110 101 011");
+        assert_eq!(search(&lines, Criteria::Most, Tie::One), Ok(0b110));
+        assert_eq!(search(&lines, Criteria::Least, Tie::One), Ok(0b011));
+    }
 }
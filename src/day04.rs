@@ -1,5 +1,5 @@
 use advent_of_code_2021::util::lines;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
 
 type Num = u8;
 
@@ -19,66 +19,197 @@ impl Board {
         self.rows.iter().map(|r| r.len()).max().unwrap()
     }
 
-    fn transpose(&self) -> Board {
-        let columns = self.columns();
+    fn cells(&self) -> impl Iterator<Item = (usize, usize, Num)> + '_ {
+        self.rows
+            .iter()
+            .enumerate()
+            .flat_map(|(r, row)| row.iter().enumerate().map(move |(c, n)| (r, c, *n)))
+    }
 
-        let rows = (0..columns)
-            .into_iter()
-            .map(|i| {
-                self.rows
-                    .iter()
-                    .map(|r| r.get(i).unwrap())
-                    .cloned()
-                    .collect()
-            })
-            .collect();
+    /// Rows may differ in length, so a column only has as many cells as
+    /// there are rows long enough to reach it.
+    fn column_len(&self, col: usize) -> usize {
+        self.rows.iter().filter(|row| row.len() > col).count()
+    }
 
-        Board { rows }
+    fn is_square(&self) -> bool {
+        let n = self.rows.len();
+        self.rows.iter().all(|row| row.len() == n)
     }
 
-    fn sets(&self) -> Vec<HashSet<Num>> {
-        let mut result = Vec::with_capacity(self.rows.len() + self.columns());
+    fn size(&self) -> usize {
+        self.rows.iter().map(|row| row.len()).sum()
+    }
+}
 
-        let rows: Vec<HashSet<Num>> = self
-            .rows
-            .iter()
-            .map(|row| row.iter().cloned().collect())
-            .collect();
+/// Scoring function shared by all rule variants: the board, its marks at the
+/// moment of winning and the last drawn number.
+type Scorer = fn(&Board, &Marks, Num) -> Score;
 
-        let columns: Vec<HashSet<Num>> = self
-            .transpose()
-            .rows
-            .iter()
-            .map(|row| row.iter().cloned().collect())
-            .collect();
+/// Sum of unmarked numbers times the last drawn number.
+fn standard(_board: &Board, marks: &Marks, last: Num) -> Score {
+    marks.unmarked * last as Score
+}
+
+#[derive(Clone, Copy)]
+struct Rules {
+    /// Both diagonals of a square board also count as winning lines.
+    diagonals: bool,
+    /// Only a fully marked board wins; rows, columns and diagonals do not.
+    blackout: bool,
+    score: Scorer,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            diagonals: false,
+            blackout: false,
+            score: standard,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Marks {
+    cells: Vec<Vec<bool>>,
+    rows: Vec<usize>,
+    columns: Vec<usize>,
+    diagonals: [usize; 2],
+    marked: usize,
+    unmarked: Score,
+    /// Shape of the board, computed once so that marking stays O(1).
+    column_lens: Vec<usize>,
+    size: usize,
+    square: bool,
+}
+
+impl Marks {
+    fn new(board: &Board) -> Self {
+        Self {
+            cells: board
+                .rows
+                .iter()
+                .map(|row| vec![false; row.len()])
+                .collect(),
+            rows: vec![0; board.rows.len()],
+            columns: vec![0; board.columns()],
+            diagonals: [0; 2],
+            marked: 0,
+            unmarked: board.flatten().map(|n| n as Score).sum(),
+            column_lens: (0..board.columns())
+                .map(|col| board.column_len(col))
+                .collect(),
+            size: board.size(),
+            square: board.is_square(),
+        }
+    }
+
+    /// Marks the cell and returns true if the board wins under given rules.
+    fn mark(&mut self, board: &Board, rules: &Rules, row: usize, col: usize) -> bool {
+        if self.cells[row][col] {
+            return false;
+        }
+        self.cells[row][col] = true;
+        self.unmarked -= board.rows[row][col] as Score;
+        self.marked += 1;
+        self.rows[row] += 1;
+        self.columns[col] += 1;
+
+        if rules.blackout {
+            return self.marked == self.size;
+        }
+
+        let n = board.rows.len();
+        let mut diagonal = false;
+        if rules.diagonals && self.square {
+            if row == col {
+                self.diagonals[0] += 1;
+                diagonal |= self.diagonals[0] == n;
+            }
+            if row + col + 1 == n {
+                self.diagonals[1] += 1;
+                diagonal |= self.diagonals[1] == n;
+            }
+        }
 
-        result.extend_from_slice(&rows);
-        result.extend_from_slice(&columns);
-        result
+        diagonal
+            || self.rows[row] == board.rows[row].len()
+            || self.columns[col] == self.column_lens[col]
+    }
+}
+
+/// Incremental bingo: each drawn number is looked up in a number -> cells
+/// index and only the affected line counters are updated.
+struct BingoGame<'a> {
+    numbers: &'a [Num],
+    boards: &'a [Board],
+    rules: Rules,
+    index: HashMap<Num, Vec<(usize, usize, usize)>>,
+    marks: Vec<Marks>,
+    won: Vec<bool>,
+    turn: usize,
+    pending: VecDeque<(usize, usize, Score)>,
+}
+
+impl<'a> BingoGame<'a> {
+    fn new(numbers: &'a [Num], boards: &'a [Board]) -> Self {
+        Self::with_rules(numbers, boards, Rules::default())
+    }
+
+    fn with_rules(numbers: &'a [Num], boards: &'a [Board], rules: Rules) -> Self {
+        let mut index: HashMap<Num, Vec<(usize, usize, usize)>> = HashMap::new();
+        for (b, board) in boards.iter().enumerate() {
+            for (r, c, n) in board.cells() {
+                index.entry(n).or_default().push((b, r, c));
+            }
+        }
+
+        Self {
+            numbers,
+            boards,
+            rules,
+            index,
+            marks: boards.iter().map(Marks::new).collect(),
+            won: vec![false; boards.len()],
+            turn: 0,
+            pending: VecDeque::new(),
+        }
     }
 
-    fn wins(&self, seq: &[Num]) -> Option<Score> {
-        let seen: HashSet<Num> = seq.iter().cloned().collect();
+    fn draw(&mut self) -> Option<Num> {
+        let number = *self.numbers.get(self.turn)?;
+        self.turn += 1;
 
-        for set in self.sets() {
-            if seen.is_superset(&set) {
-                let last = seq.last().unwrap();
-                return Some(self.score(seen, *last));
+        let mut winners = Vec::new();
+        for &(b, r, c) in self.index.get(&number).into_iter().flatten() {
+            if self.won[b] {
+                continue;
+            }
+            if self.marks[b].mark(&self.boards[b], &self.rules, r, c) {
+                self.won[b] = true;
+                winners.push(b);
             }
         }
 
-        None
+        winners.sort_unstable();
+        for b in winners {
+            let score = (self.rules.score)(&self.boards[b], &self.marks[b], number);
+            self.pending.push_back((self.turn, b, score));
+        }
+        Some(number)
     }
+}
 
-    fn score(&self, seen: HashSet<Num>, last: Num) -> Score {
-        let sum = self
-            .flatten()
-            .into_iter()
-            .filter(|n| !seen.contains(n))
-            .map(|n| n as Score)
-            .sum::<Score>();
+impl<'a> Iterator for BingoGame<'a> {
+    /// (turn, board index, score), where turn is the number of drawn numbers.
+    type Item = (usize, usize, Score);
 
-        sum * last as Score
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            self.draw()?;
+        }
+        self.pending.pop_front()
     }
 }
 
@@ -95,15 +226,9 @@ fn parse(lines: &[String]) -> (Vec<Num>, Vec<Board>) {
         .collect();
 
     let boards: Vec<Board> = split
-        .into_iter()
         .map(|rows| {
             rows.iter()
-                .map(|row| {
-                    row.split_whitespace()
-                        .into_iter()
-                        .map(|n| n.parse().unwrap())
-                        .collect()
-                })
+                .map(|row| row.split_whitespace().map(|n| n.parse().unwrap()).collect())
                 .collect()
         })
         .map(|rows| Board { rows })
@@ -112,27 +237,294 @@ fn parse(lines: &[String]) -> (Vec<Num>, Vec<Board>) {
     (numbers, boards)
 }
 
-fn wins<'a>(numbers: &'a [Num], boards: &'a [Board]) -> impl Iterator<Item = Score> + 'a {
-    (1..numbers.len()).into_iter().flat_map(|turn| {
-        let seen = &numbers[0..turn];
-        boards
-            .iter()
-            .filter(|board| {
-                let prev = &seen[0..seen.len() - 1];
-                board.wins(prev).is_none()
-            })
-            .filter_map(move |board| board.wins(seen))
-    })
+/// Builds boards and a draw sequence so that boards win exactly in `order`
+/// (a permutation of board indices), the first of them on turn `first` and
+/// every next one `side` turns later. All board numbers are distinct, so the
+/// whole input must fit into the `Num` range.
+fn generate(order: &[usize], side: usize, first: usize) -> Option<(Vec<Num>, Vec<Board>)> {
+    let count = order.len();
+    let mut sorted = order.to_vec();
+    sorted.sort_unstable();
+    if side == 0 || first < side || sorted != (0..count).collect::<Vec<_>>() {
+        return None;
+    }
+
+    let total = Num::MAX as usize + 1;
+    if count * side * side > total {
+        return None;
+    }
+    // Odd multiplier makes this a bijection on the Num range, scattering the values.
+    let value = |i: usize| ((i * 37 + 11) % total) as Num;
+
+    let boards: Vec<Board> = (0..count)
+        .map(|b| Board {
+            rows: (0..side)
+                .map(|r| {
+                    (0..side)
+                        .map(|c| value((b * side + r) * side + c))
+                        .collect()
+                })
+                .collect(),
+        })
+        .collect();
+
+    // Fillers must not complete any line, even once the first rows get drawn:
+    // cells outside the first and last rows and the last column never do.
+    let unused = (count * side * side..total).map(value);
+    let mut fillers = boards
+        .iter()
+        .flat_map(|board| {
+            board.rows[1..side.max(2) - 1]
+                .iter()
+                .flat_map(|row| row[0..side - 1].iter().cloned())
+        })
+        .chain(unused);
+
+    let mut numbers: Vec<Num> = Vec::with_capacity(total);
+    for _ in 0..first - side {
+        numbers.push(fillers.next()?);
+    }
+    for &b in order {
+        numbers.extend_from_slice(&boards[b].rows[0]);
+    }
+    // Everything left is drawn after the last board has won.
+    let drawn: HashSet<Num> = numbers.iter().cloned().collect();
+    let rest: Vec<Num> = boards
+        .iter()
+        .flat_map(|board| board.flatten())
+        .filter(|n| !drawn.contains(n))
+        .collect();
+    numbers.extend(rest);
+
+    Some((numbers, boards))
+}
+
+/// Renders the input in the same format `parse` expects.
+fn render(numbers: &[Num], boards: &[Board]) -> String {
+    let mut out = numbers
+        .iter()
+        .map(|n| n.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    for board in boards {
+        out.push('\n');
+        for row in &board.rows {
+            out.push('\n');
+            let row: Vec<String> = row.iter().map(|n| format!("{:>2}", n)).collect();
+            out.push_str(&row.join(" "));
+        }
+    }
+    out
+}
+
+/// Arguments of `day04 generate`: the comma-separated winning order, the
+/// board side and the first winning turn.
+fn generate_args(args: &[String]) -> Option<(Vec<usize>, usize, usize)> {
+    let [order, side, first] = args else {
+        return None;
+    };
+    let order = order
+        .split(',')
+        .map(|b| b.parse().ok())
+        .collect::<Option<_>>()?;
+    Some((order, side.parse().ok()?, first.parse().ok()?))
 }
 
+/// Usage: day04 [generate ORDER SIDE FIRST] < input
+///
+/// With `generate`, prints an input whose boards win in the given order
+/// instead, e.g. `generate 2,0,1 5 12`.
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(|arg| arg.as_str()) == Some("generate") {
+        let Some((order, side, first)) = generate_args(&args[1..]) else {
+            eprintln!("usage: day04 [generate ORDER SIDE FIRST]");
+            std::process::exit(2);
+        };
+        match generate(&order, side, first) {
+            Some((numbers, boards)) => println!("{}", render(&numbers, &boards)),
+            None => {
+                eprintln!("cannot generate an input for this order, side and first turn");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     let lines = lines();
     let (numbers, boards) = parse(&lines);
 
-    let mut it = wins(&numbers, &boards);
+    let mut it = BingoGame::new(&numbers, &boards).map(|(_, _, score)| score);
     let score = it.next().unwrap_or_default();
     println!("{}", score);
 
     let score = it.last().unwrap_or_default();
     println!("{}", score);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "7,4,9,5,11,17,23,2,0,14,21,24,10,16,13,6,15,25,12,22,18,20,8,19,3,26,1
+
+22 13 17 11  0
+ 8  2 23  4 24
+21  9 14 16  7
+ 6 10  3 18  5
+ 1 12 20 15 19
+
+ 3 15  0  2 22
+ 9 18 13 17  5
+19  8  7 25 23
+20 11 10 24  4
+14 21 16 12  6
+
+14 21 17 24  4
+10 16 15  9 19
+18  8 23 26 20
+22 11 13  6  5
+ 2  0 12  3  7";
+
+    fn input() -> (Vec<Num>, Vec<Board>) {
+        let lines: Vec<String> = INPUT.lines().map(|line| line.to_string()).collect();
+        parse(&lines)
+    }
+
+    #[test]
+    fn test_game() {
+        let (numbers, boards) = input();
+        let winners: Vec<_> = BingoGame::new(&numbers, &boards).collect();
+        assert_eq!(winners, vec![(12, 2, 4512), (14, 0, 2192), (15, 1, 1924)]);
+    }
+
+    #[test]
+    fn test_repeated_number() {
+        let numbers = vec![1, 1, 2];
+        let boards = vec![Board {
+            rows: vec![vec![1, 2], vec![3, 4]],
+        }];
+        let winners: Vec<_> = BingoGame::new(&numbers, &boards).collect();
+        assert_eq!(winners, vec![(3, 0, 7 * 2)]);
+    }
+
+    fn square() -> Vec<Board> {
+        vec![Board {
+            rows: vec![vec![1, 2, 3], vec![4, 5, 6], vec![7, 8, 9]],
+        }]
+    }
+
+    #[test]
+    fn test_diagonals() {
+        let boards = square();
+        let rules = Rules {
+            diagonals: true,
+            ..Rules::default()
+        };
+
+        let numbers = vec![1, 5, 9];
+        assert_eq!(BingoGame::new(&numbers, &boards).next(), None);
+        let winners: Vec<_> = BingoGame::with_rules(&numbers, &boards, rules).collect();
+        assert_eq!(winners, vec![(3, 0, 30 * 9)]);
+
+        let numbers = vec![3, 5, 7];
+        let winners: Vec<_> = BingoGame::with_rules(&numbers, &boards, rules).collect();
+        assert_eq!(winners, vec![(3, 0, 30 * 7)]);
+    }
+
+    #[test]
+    fn test_blackout() {
+        let boards = square();
+        let rules = Rules {
+            blackout: true,
+            score: |_, marks, last| marks.marked as Score * last as Score,
+            ..Rules::default()
+        };
+
+        let numbers: Vec<Num> = (1..=9).rev().collect();
+        let winners: Vec<_> = BingoGame::with_rules(&numbers, &boards, rules).collect();
+        assert_eq!(winners, vec![(9, 0, 9)]);
+    }
+
+    #[test]
+    fn test_ragged() {
+        let boards = vec![Board {
+            rows: vec![vec![1, 2, 3], vec![4, 5], vec![6]],
+        }];
+        let rules = Rules {
+            diagonals: true,
+            ..Rules::default()
+        };
+
+        let numbers = vec![3];
+        let winners: Vec<_> = BingoGame::new(&numbers, &boards).collect();
+        assert_eq!(winners, vec![(1, 0, 18 * 3)]);
+
+        let numbers = vec![2, 5];
+        let winners: Vec<_> = BingoGame::new(&numbers, &boards).collect();
+        assert_eq!(winners, vec![(2, 0, 14 * 5)]);
+
+        let numbers = vec![1, 5];
+        let winners: Vec<_> = BingoGame::with_rules(&numbers, &boards, rules).collect();
+        assert_eq!(winners, vec![]);
+    }
+
+    #[test]
+    fn test_generate() {
+        let cases = vec![
+            (vec![0], 5, 5),
+            (vec![2, 0, 1], 5, 5),
+            (vec![2, 0, 1], 5, 12),
+            (vec![3, 1, 0, 2], 3, 20),
+            (vec![1, 0], 1, 7),
+        ];
+
+        for (order, side, first) in cases {
+            let (numbers, boards) = generate(&order, side, first).unwrap();
+            let text = render(&numbers, &boards);
+            let lines: Vec<String> = text.lines().map(|line| line.to_string()).collect();
+            let (numbers, boards) = parse(&lines);
+            assert_eq!(boards.len(), order.len());
+
+            let winners: Vec<_> = BingoGame::new(&numbers, &boards)
+                .map(|(turn, board, _)| (turn, board))
+                .collect();
+            let expected: Vec<_> = order
+                .iter()
+                .enumerate()
+                .map(|(k, board)| (first + k * side, *board))
+                .collect();
+            assert_eq!(
+                winners, expected,
+                "order={:?} side={} first={}",
+                order, side, first
+            );
+        }
+    }
+
+    #[test]
+    fn test_generate_args() {
+        let args = |s: &str| {
+            s.split_whitespace()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            generate_args(&args("2,0,1 5 12")),
+            Some((vec![2, 0, 1], 5, 12))
+        );
+        assert_eq!(generate_args(&args("0 5 5")), Some((vec![0], 5, 5)));
+        for bad in ["2,,1 5 12", "2,0 5", "2,0 5 x", "0 5 5 5"] {
+            assert_eq!(generate_args(&args(bad)), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_generate_invalid() {
+        assert!(generate(&[0, 0], 5, 5).is_none());
+        assert!(generate(&[1, 2], 5, 5).is_none());
+        assert!(generate(&[0], 5, 4).is_none());
+        assert!(generate(&(0..11).collect::<Vec<_>>(), 5, 5).is_none());
+        assert!(generate(&[0], 5, 300).is_none());
+    }
+}
@@ -1,10 +1,10 @@
 use advent_of_code_2021::util::input;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::str::FromStr;
 
 type Num = i32;
 
-#[derive(Debug, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
 struct Dot {
     x: Num,
     y: Num,
@@ -14,6 +14,10 @@ impl Dot {
     fn of(x: Num, y: Num) -> Self {
         Dot { x, y }
     }
+
+    fn wide(&self) -> (Wide, Wide) {
+        (self.x as Wide, self.y as Wide)
+    }
 }
 
 #[derive(Debug)]
@@ -35,45 +39,278 @@ impl Line {
         self.at.y == self.to.y
     }
 
+    /// All integer lattice points of the segment, for any slope: the
+    /// direction vector reduced by gcd is the smallest step between them.
     fn dots(&self) -> Vec<Dot> {
-        if self.is_horizontal() {
-            assert_eq!(self.at.y, self.to.y);
-            let lo = self.at.x.min(self.to.x);
-            let hi = self.at.x.max(self.to.x);
-            let y = self.at.y;
-            return (lo..=hi).into_iter().map(|x| Dot::of(x, y)).collect();
-        }
-
-        if self.is_vertical() {
-            assert_eq!(self.at.x, self.to.x);
-            let lo = self.at.y.min(self.to.y);
-            let hi = self.at.y.max(self.to.y);
-            let x = self.at.x;
-            return (lo..=hi).into_iter().map(|y| Dot::of(x, y)).collect();
-        }
-
         let dx = self.to.x - self.at.x;
         let dy = self.to.y - self.at.y;
-        assert_eq!(dx.abs(), dy.abs());
+        let n = gcd(dx, dy);
+        if n == 0 {
+            return vec![Dot::of(self.at.x, self.at.y)];
+        }
 
-        (0..=dx.abs())
-            .into_iter()
-            .map(|d| Dot::of(self.at.x + d * dx.signum(), self.at.y + d * dy.signum()))
+        let (sx, sy) = (dx / n, dy / n);
+        (0..=n)
+            .map(|d| Dot::of(self.at.x + d * sx, self.at.y + d * sy))
             .collect()
     }
 }
 
-#[allow(dead_code)] // unnecessary for diagonal lines only
 fn gcd(a: Num, b: Num) -> Num {
-    assert!(a >= b);
-    let r = a % b;
-    if r == 0 {
-        b
+    if b == 0 {
+        a.abs()
     } else {
-        gcd(b, r)
+        gcd(b, a % b)
+    }
+}
+
+type Wide = i64;
+
+fn cross(a: (Wide, Wide), b: (Wide, Wide)) -> Wide {
+    a.0 * b.1 - a.1 * b.0
+}
+
+/// The infinite line a segment lies on: reduced direction, normalized to
+/// point right (or up, if vertical), and the offset constant along it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+struct Support {
+    dir: (Wide, Wide),
+    offset: Wide,
+}
+
+impl Support {
+    /// Lattice points on the line are ordered by their projection onto the
+    /// direction, consecutive points being exactly `step` apart.
+    fn step(&self) -> Wide {
+        self.dir.0 * self.dir.0 + self.dir.1 * self.dir.1
+    }
+
+    fn project(&self, (x, y): (Wide, Wide)) -> Wide {
+        self.dir.0 * x + self.dir.1 * y
     }
 }
 
+/// Segment as `at + t * support.dir` for `t` in `0..=len`.
+#[derive(Debug)]
+struct Segment {
+    at: (Wide, Wide),
+    len: Wide,
+    support: Support,
+}
+
+impl Segment {
+    fn of(line: &Line) -> Self {
+        let (mut at, mut to) = (line.at.wide(), line.to.wide());
+        let n = gcd(line.to.x - line.at.x, line.to.y - line.at.y) as Wide;
+        let dir = if n == 0 {
+            (1, 0)
+        } else {
+            let dir = ((to.0 - at.0) / n, (to.1 - at.1) / n);
+            if dir.0 > 0 || (dir.0 == 0 && dir.1 > 0) {
+                dir
+            } else {
+                std::mem::swap(&mut at, &mut to);
+                (-dir.0, -dir.1)
+            }
+        };
+        let offset = dir.0 * at.1 - dir.1 * at.0;
+        Segment {
+            at,
+            len: n,
+            support: Support { dir, offset },
+        }
+    }
+
+    fn to(&self) -> (Wide, Wide) {
+        let (dx, dy) = self.support.dir;
+        (self.at.0 + self.len * dx, self.at.1 + self.len * dy)
+    }
+
+    /// Closed interval of projections covered by the segment.
+    fn span(&self) -> (Wide, Wide) {
+        let lo = self.support.project(self.at);
+        (lo, lo + self.len * self.support.step())
+    }
+
+    /// Lattice point where two non-parallel segments cross, if any.
+    fn cross(&self, that: &Segment) -> Option<(Wide, Wide)> {
+        let (u, v) = (self.support.dir, that.support.dir);
+        let den = cross(u, v);
+        if den == 0 {
+            return None;
+        }
+        let d = (that.at.0 - self.at.0, that.at.1 - self.at.1);
+        let (t, s) = (cross(d, v), cross(d, u));
+        if t % den != 0 || s % den != 0 {
+            return None;
+        }
+        let (t, s) = (t / den, s / den);
+        if t < 0 || t > self.len || s < 0 || s > that.len {
+            return None;
+        }
+        Some((self.at.0 + t * u.0, self.at.1 + t * u.1))
+    }
+}
+
+/// Closed intervals (in projection units) covered by at least `k` of the
+/// given intervals on the same line.
+fn covered(spans: &[(Wide, Wide)], step: Wide, k: i32) -> Vec<(Wide, Wide)> {
+    let mut events: Vec<(Wide, i32)> = spans
+        .iter()
+        .flat_map(|(lo, hi)| [(*lo, 1), (*hi + step, -1)])
+        .collect();
+    events.sort_unstable();
+
+    let mut result = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (at, delta) in events {
+        let was = depth;
+        depth += delta;
+        if was < k && depth >= k {
+            start = at;
+        }
+        if was >= k && depth < k {
+            result.push((start, at - step));
+        }
+    }
+    result
+}
+
+fn covers(doubles: &[(Wide, Wide)], at: Wide) -> bool {
+    let i = doubles.partition_point(|(lo, _)| *lo <= at);
+    i > 0 && doubles[i - 1].1 >= at
+}
+
+/// Calls `f` for each pair of segments from `us` and `vs` that intersect,
+/// where all of `us` share one direction `u` and all of `vs` another one `v`.
+///
+/// In the coordinates `(cross(u, p), cross(v, p))` the first ones are
+/// vertical and the others horizontal, so a sweep over the first coordinate
+/// with the active horizontal segments ordered by the second one finds them
+/// in O((n + k) log n). Segments of the same direction must not overlap.
+fn intersections<F>(us: &[Segment], vs: &[Segment], mut f: F)
+where
+    F: FnMut(&Segment, &Segment),
+{
+    let (u, v) = (us[0].support.dir, vs[0].support.dir);
+    let ends = |segment: &Segment, dir: (Wide, Wide)| {
+        let (a, b) = (cross(dir, segment.at), cross(dir, segment.to()));
+        (a.min(b), a.max(b))
+    };
+
+    // Inserts come before queries and removals after them at the same
+    // coordinate, as segments are closed.
+    let mut events: Vec<(Wide, u8, usize)> = Vec::new();
+    for (i, segment) in vs.iter().enumerate() {
+        let (lo, hi) = ends(segment, u);
+        events.push((lo, 0, i));
+        events.push((hi, 2, i));
+    }
+    for (i, segment) in us.iter().enumerate() {
+        events.push((segment.support.offset, 1, i));
+    }
+    events.sort_unstable();
+
+    // Segments of one direction are disjoint, so at most one per offset is
+    // active at a time.
+    let mut active: BTreeMap<Wide, usize> = BTreeMap::new();
+    for (_, kind, i) in events {
+        match kind {
+            0 => {
+                active.insert(vs[i].support.offset, i);
+            }
+            1 => {
+                let (lo, hi) = ends(&us[i], v);
+                for (_, j) in active.range(lo..=hi) {
+                    f(&us[i], &vs[*j]);
+                }
+            }
+            _ => {
+                active.remove(&vs[i].support.offset);
+            }
+        }
+    }
+}
+
+/// Counts lattice points covered by at least two lines. Collinear overlaps
+/// are counted as intervals per supporting line. Crossings are then found
+/// between the merged segments of each pair of directions, so the cost is
+/// O((d n + k) log n) for n segments, k crossing pairs and d distinct
+/// directions, e.g. d <= 4 for horizontal, vertical and diagonal lines.
+fn sweep<'a>(lines: impl Iterator<Item = &'a Line>) -> usize {
+    let mut spans: HashMap<Support, Vec<(Wide, Wide)>> = HashMap::new();
+    let mut origins: HashMap<Support, (Wide, Wide)> = HashMap::new();
+    for segment in lines.map(Segment::of) {
+        origins.entry(segment.support).or_insert(segment.at);
+        spans
+            .entry(segment.support)
+            .or_default()
+            .push(segment.span());
+    }
+
+    let doubles: HashMap<Support, Vec<(Wide, Wide)>> = spans
+        .iter()
+        .map(|(support, spans)| (*support, covered(spans, support.step(), 2)))
+        .collect();
+    let overlap =
+        |support: &Support, at: (Wide, Wide)| covers(&doubles[support], support.project(at));
+
+    // The union of the segments on each line, as disjoint segments grouped
+    // by direction.
+    let mut directions: HashMap<(Wide, Wide), Vec<Segment>> = HashMap::new();
+    for (support, spans) in spans.iter() {
+        let origin = origins[support];
+        let (step, base) = (support.step(), support.project(origin));
+        for (lo, hi) in covered(spans, step, 1) {
+            let t = (lo - base) / step;
+            let (dx, dy) = support.dir;
+            directions.entry(support.dir).or_default().push(Segment {
+                at: (origin.0 + t * dx, origin.1 + t * dy),
+                len: (hi - lo) / step,
+                support: *support,
+            });
+        }
+    }
+    let directions: Vec<Vec<Segment>> = directions.into_values().collect();
+
+    // Supports whose collinear overlaps already contain the crossing point.
+    let mut crossings: HashMap<(Wide, Wide), Vec<Support>> = HashMap::new();
+    for (i, us) in directions.iter().enumerate() {
+        for vs in directions[i + 1..].iter() {
+            intersections(us, vs, |this, that| {
+                if let Some(at) = this.cross(that) {
+                    let seen = crossings.entry(at).or_default();
+                    for support in [this.support, that.support] {
+                        if overlap(&support, at) && !seen.contains(&support) {
+                            seen.push(support);
+                        }
+                    }
+                }
+            });
+        }
+    }
+
+    let collinear = doubles
+        .iter()
+        .flat_map(|(support, doubles)| {
+            doubles
+                .iter()
+                .map(move |(lo, hi)| ((hi - lo) / support.step() + 1) as usize)
+        })
+        .sum::<usize>();
+    // A crossing point is counted once: either it is new, or it is already
+    // counted by each of the collinear overlaps it lies in.
+    let (new, repeated) =
+        crossings
+            .values()
+            .fold((0, 0), |(new, repeated), seen| match seen.len() {
+                0 => (new + 1, repeated),
+                k => (new, repeated + k - 1),
+            });
+    collinear + new - repeated
+}
+
 impl FromStr for Line {
     type Err = ();
 
@@ -89,29 +326,175 @@ impl FromStr for Line {
     }
 }
 
-fn solve<'a>(lines: impl Iterator<Item = &'a Line>) -> usize {
-    lines
-        .flat_map(|line| line.dots())
-        .fold(HashMap::new(), |mut map, dot| {
-            *map.entry(dot).or_insert(0) += 1;
-            map
-        })
-        .into_iter()
-        .filter(|(_, count)| *count > 1)
-        .count()
+/// Number of lines covering each point.
+#[derive(Debug, Default)]
+struct Heatmap {
+    counts: HashMap<Dot, usize>,
+}
+
+impl Heatmap {
+    fn of<'a>(lines: impl Iterator<Item = &'a Line>) -> Self {
+        let counts = lines
+            .flat_map(|line| line.dots())
+            .fold(HashMap::new(), |mut map, dot| {
+                *map.entry(dot).or_insert(0) += 1;
+                map
+            });
+        Self { counts }
+    }
+
+    fn at(&self, dot: &Dot) -> usize {
+        self.counts.get(dot).cloned().unwrap_or_default()
+    }
+
+    /// Points covered by at least `k` lines, in reading order.
+    fn at_least(&self, k: usize) -> Vec<Dot> {
+        let mut dots: Vec<Dot> = self
+            .counts
+            .iter()
+            .filter(|(_, count)| **count >= k)
+            .map(|(dot, _)| *dot)
+            .collect();
+        dots.sort_by_key(|dot| (dot.y, dot.x));
+        dots
+    }
+
+    /// Top `n` points by coverage, ties broken in reading order.
+    fn hottest(&self, n: usize) -> Vec<(Dot, usize)> {
+        let mut dots: Vec<(Dot, usize)> = self.counts.iter().map(|(d, c)| (*d, *c)).collect();
+        dots.sort_by_key(|(dot, count)| (std::cmp::Reverse(*count), dot.y, dot.x));
+        dots.truncate(n);
+        dots
+    }
+
+    /// Rendered area: from the origin (or the lowest negative coordinate)
+    /// to the highest covered point, like the example map.
+    fn bounds(&self) -> (Dot, Dot) {
+        let dots = self.counts.keys();
+        let lo = dots
+            .clone()
+            .fold(Dot::of(0, 0), |lo, d| Dot::of(lo.x.min(d.x), lo.y.min(d.y)));
+        let hi = dots.fold(Dot::of(0, 0), |hi, d| Dot::of(hi.x.max(d.x), hi.y.max(d.y)));
+        (lo, hi)
+    }
+
+    /// `.` for uncovered points, coverage count otherwise (`#` above 9).
+    fn ascii(&self) -> String {
+        let (lo, hi) = self.bounds();
+        (lo.y..=hi.y)
+            .map(|y| {
+                (lo.x..=hi.x)
+                    .map(|x| match self.at(&Dot::of(x, y)) {
+                        0 => '.',
+                        n if n < 10 => (b'0' + n as u8) as char,
+                        _ => '#',
+                    })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Plain (P2) PGM image, brightness proportional to coverage.
+    fn pgm(&self) -> String {
+        let (lo, hi) = self.bounds();
+        let max = self
+            .counts
+            .values()
+            .max()
+            .cloned()
+            .unwrap_or_default()
+            .max(1);
+        let mut out = format!("P2\n{} {}\n{}\n", hi.x - lo.x + 1, hi.y - lo.y + 1, max);
+        for y in lo.y..=hi.y {
+            let row: Vec<String> = (lo.x..=hi.x)
+                .map(|x| self.at(&Dot::of(x, y)).to_string())
+                .collect();
+            out.push_str(&row.join(" "));
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// Heatmap rendering or density query.
+#[derive(Debug, Eq, PartialEq)]
+enum Query {
+    Ascii,
+    Pgm,
+    Hottest(usize),
+    AtLeast(usize),
+    At(Dot),
 }
 
+impl Query {
+    fn parse(args: &[String]) -> Result<Self, ()> {
+        let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+        fn number<T: FromStr>(s: &str) -> Result<T, ()> {
+            s.parse().map_err(|_| ())
+        }
+        match args[..] {
+            ["ascii"] => Ok(Query::Ascii),
+            ["pgm"] => Ok(Query::Pgm),
+            ["hottest", n] => Ok(Query::Hottest(number(n)?)),
+            ["at-least", k] => Ok(Query::AtLeast(number(k)?)),
+            ["at", dot] => {
+                let (x, y) = dot.split_once(',').ok_or(())?;
+                Ok(Query::At(Dot::of(number(x)?, number(y)?)))
+            }
+            _ => Err(()),
+        }
+    }
+}
+
+/// Rendering, or one `x,y count` line per point found by the query.
+fn answer(map: &Heatmap, query: &Query) -> String {
+    let dots = match query {
+        Query::Ascii => return map.ascii() + "\n",
+        Query::Pgm => return map.pgm(),
+        Query::At(dot) => vec![(*dot, map.at(dot))],
+        Query::Hottest(n) => map.hottest(*n),
+        Query::AtLeast(k) => {
+            let dots = map.at_least(*k).into_iter();
+            dots.map(|dot| (dot, map.at(&dot))).collect()
+        }
+    };
+    dots.iter()
+        .map(|(dot, count)| format!("{},{} {}\n", dot.x, dot.y, count))
+        .collect()
+}
+
+/// Usage: day05 [ascii | pgm | hottest N | at-least K | at X,Y] < input
+///
+/// With an argument, builds the heatmap of all lines and renders or queries
+/// it instead: the coverage at a point, the points covered at least `K`
+/// times or the `N` hottest points, each as `x,y count`.
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let query = match Query::parse(&args) {
+        Ok(query) => Some(query),
+        Err(()) if args.is_empty() => None,
+        Err(()) => {
+            eprintln!("usage: day05 [ascii | pgm | hottest N | at-least K | at X,Y]");
+            std::process::exit(2);
+        }
+    };
+
     let lines: Vec<Line> = input();
 
-    let n = solve(
+    if let Some(query) = query {
+        print!("{}", answer(&Heatmap::of(lines.iter()), &query));
+        return;
+    }
+
+    let n = sweep(
         lines
             .iter()
             .filter(|line| line.is_horizontal() || line.is_vertical()),
     );
     println!("{}", n);
 
-    let n = solve(lines.iter());
+    let n = sweep(lines.iter());
     println!("{}", n);
 }
 
@@ -119,9 +502,21 @@ fn main() {
 mod tests {
     use super::*;
 
+    /// Reference solution, memory grows with total line length.
+    fn solve<'a>(lines: impl Iterator<Item = &'a Line>) -> usize {
+        Heatmap::of(lines).at_least(2).len()
+    }
+
     #[test]
     fn test_gcd() {
-        let cases = vec![(54, 24, 6)];
+        let cases = vec![
+            (54, 24, 6),
+            (24, 54, 6),
+            (-4, 6, 2),
+            (0, -3, 3),
+            (5, 0, 5),
+            (0, 0, 0),
+        ];
 
         for (a, b, expected) in cases {
             let actual = gcd(a, b);
@@ -140,6 +535,23 @@ mod tests {
                 Line::of(Dot::of(9, 7), Dot::of(7, 9)),
                 vec![Dot::of(9, 7), Dot::of(8, 8), Dot::of(7, 9)],
             ),
+            (
+                Line::of(Dot::of(0, 9), Dot::of(2, 9)),
+                vec![Dot::of(0, 9), Dot::of(1, 9), Dot::of(2, 9)],
+            ),
+            (
+                Line::of(Dot::of(1, 3), Dot::of(1, 1)),
+                vec![Dot::of(1, 3), Dot::of(1, 2), Dot::of(1, 1)],
+            ),
+            (
+                Line::of(Dot::of(0, 0), Dot::of(6, 3)),
+                vec![Dot::of(0, 0), Dot::of(2, 1), Dot::of(4, 2), Dot::of(6, 3)],
+            ),
+            (
+                Line::of(Dot::of(5, 1), Dot::of(-1, 5)),
+                vec![Dot::of(5, 1), Dot::of(2, 3), Dot::of(-1, 5)],
+            ),
+            (Line::of(Dot::of(4, 4), Dot::of(4, 4)), vec![Dot::of(4, 4)]),
         ];
 
         for (line, expected) in cases {
@@ -147,4 +559,199 @@ mod tests {
             assert_eq!(actual, expected);
         }
     }
+
+    #[test]
+    fn test_solve_any_slope() {
+        let lines = [
+            Line::of(Dot::of(0, 0), Dot::of(6, 3)),
+            Line::of(Dot::of(4, 0), Dot::of(4, 4)),
+            Line::of(Dot::of(0, 3), Dot::of(6, 0)),
+        ];
+        // The slanted lines cross between lattice points, the vertical one
+        // meets them at (4, 2) and (4, 1).
+        assert_eq!(solve(lines.iter()), 2);
+    }
+
+    const EXAMPLE: [&str; 10] = [
+        "0,9 -> 5,9",
+        "8,0 -> 0,8",
+        "9,4 -> 3,4",
+        "2,2 -> 2,1",
+        "7,0 -> 7,4",
+        "6,4 -> 2,0",
+        "0,9 -> 2,9",
+        "3,4 -> 1,4",
+        "0,0 -> 8,8",
+        "5,5 -> 8,2",
+    ];
+
+    fn example() -> Vec<Line> {
+        EXAMPLE.iter().map(|line| line.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_sweep() {
+        let lines = example();
+        let straight = || {
+            lines
+                .iter()
+                .filter(|line| line.is_horizontal() || line.is_vertical())
+        };
+        assert_eq!(sweep(straight()), 5);
+        assert_eq!(sweep(lines.iter()), 12);
+    }
+
+    #[test]
+    fn test_sweep_matches_solve() {
+        let cases: [&[&str]; 8] = [
+            &EXAMPLE,
+            // Stacked parallel lines, crossed by one vertical line.
+            &[
+                "0,0 -> 9,0",
+                "0,1 -> 9,1",
+                "0,2 -> 9,2",
+                "4,0 -> 4,2",
+                "0,4 -> 9,4",
+            ],
+            // Crossings inside and outside of collinear overlaps.
+            &[
+                "0,0 -> 6,0",
+                "3,0 -> 9,0",
+                "4,-3 -> 4,3",
+                "1,-3 -> 7,3",
+                "8,-1 -> 8,1",
+            ],
+            // Assorted slopes, some crossing between lattice points.
+            &[
+                "0,0 -> 6,3",
+                "4,0 -> 4,4",
+                "0,3 -> 6,0",
+                "0,0 -> 6,6",
+                "5,1 -> -1,5",
+                "2,1 -> 2,1",
+                "3,3 -> 3,3",
+            ],
+            // Many lines through one point.
+            &[
+                "0,5 -> 10,5",
+                "5,0 -> 5,10",
+                "0,0 -> 10,10",
+                "0,10 -> 10,0",
+                "1,3 -> 9,7",
+                "5,5 -> 5,5",
+            ],
+            // Segments meeting at their ends.
+            &[
+                "0,0 -> 3,0",
+                "3,0 -> 3,3",
+                "3,3 -> 0,0",
+                "0,3 -> 3,0",
+                "0,3 -> 0,0",
+            ],
+            // Adjacent collinear segments that do not overlap.
+            &[
+                "0,0 -> 2,0",
+                "3,0 -> 5,0",
+                "2,-1 -> 2,1",
+                "3,-1 -> 3,1",
+                "6,0 -> 6,0",
+            ],
+            // The same segment in both directions.
+            &[
+                "5,0 -> 0,5",
+                "0,5 -> 5,0",
+                "1,1 -> 4,4",
+                "4,4 -> 1,1",
+                "2,3 -> 2,3",
+            ],
+        ];
+        for lines in cases {
+            let lines: Vec<Line> = lines.iter().map(|line| line.parse().unwrap()).collect();
+            assert_eq!(sweep(lines.iter()), solve(lines.iter()), "{:?}", lines);
+        }
+    }
+
+    #[test]
+    fn test_sweep_stacked() {
+        // Long parallel lines that never meet each other, crossed by a
+        // single vertical one.
+        let n = 20_000;
+        let mut lines: Vec<Line> = (0..n)
+            .map(|y| Line::of(Dot::of(0, y), Dot::of(1_000_000_000, y)))
+            .collect();
+        lines.push(Line::of(
+            Dot::of(500_000_000, 0),
+            Dot::of(500_000_000, n - 1),
+        ));
+        assert_eq!(sweep(lines.iter()), n as usize);
+    }
+
+    #[test]
+    fn test_sweep_huge() {
+        let lines = [
+            Line::of(Dot::of(0, 0), Dot::of(2_000_000_000, 0)),
+            Line::of(Dot::of(1_000_000_000, 0), Dot::of(2_000_000_000, 0)),
+            Line::of(Dot::of(0, -1_000_000), Dot::of(2_000_000, 1_000_000)),
+        ];
+        // The collinear overlap plus the crossing at (1_000_000, 0).
+        assert_eq!(sweep(lines.iter()), 1_000_000_002);
+    }
+
+    #[test]
+    fn test_heatmap() {
+        let lines = example();
+        let map = Heatmap::of(lines.iter());
+
+        assert_eq!(map.at(&Dot::of(4, 4)), 3);
+        assert_eq!(map.at(&Dot::of(1, 0)), 0);
+        assert_eq!(map.at_least(2).len(), 12);
+        assert_eq!(map.at_least(3), vec![Dot::of(4, 4), Dot::of(6, 4)]);
+        assert_eq!(
+            map.hottest(3),
+            vec![(Dot::of(4, 4), 3), (Dot::of(6, 4), 3), (Dot::of(7, 1), 2)]
+        );
+
+        let expected = "\
+1.1....11.
+.111...2..
+..2.1.111.
+...1.2.2..
+.112313211
+...1.2....
+..1...1...
+.1.....1..
+1.......1.
+222111....";
+        assert_eq!(map.ascii(), expected);
+
+        let pgm = map.pgm();
+        let mut it = pgm.lines();
+        assert_eq!(it.next(), Some("P2"));
+        assert_eq!(it.next(), Some("10 10"));
+        assert_eq!(it.next(), Some("3"));
+        assert_eq!(it.nth(4), Some("0 1 1 2 3 1 3 2 1 1"));
+    }
+
+    #[test]
+    fn test_query() {
+        let args = |s: &str| {
+            s.split_whitespace()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(Query::parse(&args("pgm")), Ok(Query::Pgm));
+        assert_eq!(Query::parse(&args("hottest 3")), Ok(Query::Hottest(3)));
+        assert_eq!(
+            Query::parse(&args("at -1,5")),
+            Ok(Query::At(Dot::of(-1, 5)))
+        );
+        for bad in ["", "hottest", "at-least x", "at 1", "ascii 2"] {
+            assert_eq!(Query::parse(&args(bad)), Err(()), "{}", bad);
+        }
+
+        let map = Heatmap::of(example().iter());
+        assert_eq!(answer(&map, &Query::At(Dot::of(7, 4))), "7,4 2\n");
+        assert_eq!(answer(&map, &Query::AtLeast(3)), "4,4 3\n6,4 3\n");
+        assert!(answer(&map, &Query::Ascii).starts_with("1.1....11.\n"));
+    }
 }
@@ -1,37 +1,624 @@
 use advent_of_code_2021::util::lines;
+use std::fmt;
 
 type Num = usize;
 
-fn day(bins: &mut [Num; 9]) {
-    let ready = bins[0];
-    for i in 0..bins.len() - 1 {
-        bins[i] = bins[i + 1];
+/// Values the population can be counted in. Zero and one are taken from an
+/// existing value, so that a modulus can travel along with it.
+trait Ring: Clone {
+    fn zero(&self) -> Self;
+    fn one(&self) -> Self;
+    fn add(&self, that: &Self) -> Self;
+    fn mul(&self, that: &Self) -> Self;
+}
+
+impl Ring for Num {
+    fn zero(&self) -> Self {
+        0
+    }
+
+    fn one(&self) -> Self {
+        1
+    }
+
+    fn add(&self, that: &Self) -> Self {
+        self + that
+    }
+
+    fn mul(&self, that: &Self) -> Self {
+        self * that
+    }
+}
+
+/// Residue modulo a user-given (prime) modulus.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct Mod {
+    value: u64,
+    modulus: u64,
+}
+
+impl Mod {
+    fn of(value: u64, modulus: u64) -> Self {
+        assert!(modulus > 0);
+        Self {
+            value: value % modulus,
+            modulus,
+        }
+    }
+}
+
+impl fmt::Display for Mod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.value)
+    }
+}
+
+impl Ring for Mod {
+    fn zero(&self) -> Self {
+        Mod::of(0, self.modulus)
+    }
+
+    fn one(&self) -> Self {
+        Mod::of(1, self.modulus)
+    }
+
+    fn add(&self, that: &Self) -> Self {
+        let value = (self.value as u128 + that.value as u128) % self.modulus as u128;
+        Mod::of(value as u64, self.modulus)
+    }
+
+    fn mul(&self, that: &Self) -> Self {
+        let value = (self.value as u128 * that.value as u128) % self.modulus as u128;
+        Mod::of(value as u64, self.modulus)
+    }
+}
+
+/// Arbitrary-precision unsigned integer, little-endian limbs in base 10^9.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Big {
+    limbs: Vec<u32>,
+}
+
+const BASE: u64 = 1_000_000_000;
+
+impl Big {
+    fn of(mut n: u64) -> Self {
+        let mut limbs = Vec::new();
+        while n > 0 {
+            limbs.push((n % BASE) as u32);
+            n /= BASE;
+        }
+        Self { limbs }
+    }
+}
+
+impl Ring for Big {
+    fn zero(&self) -> Self {
+        Big::of(0)
+    }
+
+    fn one(&self) -> Self {
+        Big::of(1)
+    }
+
+    fn add(&self, that: &Self) -> Self {
+        let len = self.limbs.len().max(that.limbs.len());
+        let mut limbs = Vec::with_capacity(len + 1);
+        let mut carry = 0u64;
+        for i in 0..len {
+            let a = self.limbs.get(i).cloned().unwrap_or_default() as u64;
+            let b = that.limbs.get(i).cloned().unwrap_or_default() as u64;
+            let sum = a + b + carry;
+            limbs.push((sum % BASE) as u32);
+            carry = sum / BASE;
+        }
+        if carry > 0 {
+            limbs.push(carry as u32);
+        }
+        Self { limbs }
+    }
+
+    fn mul(&self, that: &Self) -> Self {
+        if self.limbs.is_empty() || that.limbs.is_empty() {
+            return self.zero();
+        }
+        let mut acc = vec![0u64; self.limbs.len() + that.limbs.len()];
+        for (i, a) in self.limbs.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, b) in that.limbs.iter().enumerate() {
+                let cur = acc[i + j] + *a as u64 * *b as u64 + carry;
+                acc[i + j] = cur % BASE;
+                carry = cur / BASE;
+            }
+            acc[i + that.limbs.len()] += carry;
+        }
+        while acc.last() == Some(&0) {
+            acc.pop();
+        }
+        Self {
+            limbs: acc.into_iter().map(|limb| limb as u32).collect(),
+        }
+    }
+}
+
+impl fmt::Display for Big {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.limbs.split_last() {
+            None => write!(f, "0"),
+            Some((head, tail)) => {
+                write!(f, "{}", head)?;
+                for limb in tail.iter().rev() {
+                    write!(f, "{:09}", limb)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+type Matrix<T> = Vec<Vec<T>>;
+
+fn multiply<T: Ring>(a: &Matrix<T>, b: &Matrix<T>) -> Matrix<T> {
+    let n = a.len();
+    let zero = a[0][0].zero();
+    (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| (0..n).fold(zero.clone(), |acc, k| acc.add(&a[i][k].mul(&b[k][j]))))
+                .collect()
+        })
+        .collect()
+}
+
+fn power<T: Ring>(m: &Matrix<T>, mut exp: u64) -> Matrix<T> {
+    let n = m.len();
+    let (zero, one) = (m[0][0].zero(), m[0][0].one());
+    let mut result: Matrix<T> = (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| if i == j { one.clone() } else { zero.clone() })
+                .collect()
+        })
+        .collect();
+    let mut base = m.clone();
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = multiply(&result, &base);
+        }
+        exp >>= 1;
+        if exp > 0 {
+            base = multiply(&base, &base);
+        }
+    }
+    result
+}
+
+/// Lifecycle of a fish species. A fish with timer 0 spawns and restarts at
+/// `cycle - 1`, a newborn starts `delay` days later than that. With a
+/// `lifespan`, a fish dies on reaching that age (fish in the input are
+/// considered newly adult, i.e. of age 0).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct Species {
+    cycle: usize,
+    delay: usize,
+    lifespan: Option<usize>,
+}
+
+impl Species {
+    const LANTERNFISH: Species = Species {
+        cycle: 7,
+        delay: 2,
+        lifespan: None,
+    };
+
+    /// Number of distinct timer values.
+    fn timers(&self) -> usize {
+        self.cycle + self.delay
     }
-    bins[6] += ready;
-    bins[8] = ready;
+
+    /// Number of tracked ages: without a lifespan age does not matter.
+    fn ages(&self) -> usize {
+        self.lifespan.unwrap_or(1)
+    }
+
+    fn states(&self) -> usize {
+        self.ages() * self.timers()
+    }
+
+    fn index(&self, age: usize, timer: usize) -> usize {
+        age * self.timers() + timer
+    }
+
+    /// Initial bins for the given timers, one state per (age, timer).
+    fn bins<T: Ring>(&self, timers: &[usize], unit: &T) -> Vec<T> {
+        assert!(self.cycle > 0);
+        assert!(self.lifespan != Some(0));
+        let mut bins = vec![unit.zero(); self.states()];
+        for timer in timers {
+            assert!(*timer < self.timers(), "timer {} is out of range", timer);
+            let i = self.index(0, *timer);
+            bins[i] = bins[i].add(&unit.one());
+        }
+        bins
+    }
+
+    /// Where a fish in the given state moves to after one day (if it
+    /// survives) and whether it spawns a newborn.
+    fn next(&self, age: usize, timer: usize) -> (Option<usize>, bool) {
+        let spawn = timer == 0;
+        let timer = if spawn { self.cycle - 1 } else { timer - 1 };
+        let age = match self.lifespan {
+            Some(lifespan) if age + 1 >= lifespan => None,
+            Some(_) => Some(age + 1),
+            None => Some(0),
+        };
+        (age.map(|age| self.index(age, timer)), spawn)
+    }
+
+    fn newborn(&self) -> usize {
+        self.index(0, self.timers() - 1)
+    }
+
+    fn moves(&self) -> impl Iterator<Item = (usize, Option<usize>, bool)> + '_ {
+        (0..self.ages()).flat_map(move |age| {
+            (0..self.timers()).map(move |timer| {
+                let (next, spawn) = self.next(age, timer);
+                (self.index(age, timer), next, spawn)
+            })
+        })
+    }
+
+    fn day<T: Ring>(&self, bins: &[T]) -> Vec<T> {
+        let mut next = vec![bins[0].zero(); bins.len()];
+        for (from, to, spawn) in self.moves() {
+            if let Some(to) = to {
+                next[to] = next[to].add(&bins[from]);
+            }
+            if spawn {
+                let i = self.newborn();
+                next[i] = next[i].add(&bins[from]);
+            }
+        }
+        next
+    }
+
+    /// Population for each day from 0 to `days` inclusive.
+    fn series<T: Ring>(&self, bins: &[T], days: usize) -> Vec<T> {
+        let zero = bins[0].zero();
+        let total = |bins: &[T]| bins.iter().fold(zero.clone(), |acc, x| acc.add(x));
+        let mut bins = bins.to_vec();
+        let mut result = vec![total(&bins)];
+        for _ in 0..days {
+            bins = self.day(&bins);
+            result.push(total(&bins));
+        }
+        result
+    }
+
+    /// One day as a matrix acting on the bins: `next = M * bins`.
+    fn transition<T: Ring>(&self, unit: &T) -> Matrix<T> {
+        let n = self.states();
+        let mut m = vec![vec![unit.zero(); n]; n];
+        for (from, to, spawn) in self.moves() {
+            if let Some(to) = to {
+                m[to][from] = m[to][from].add(&unit.one());
+            }
+            if spawn {
+                let i = self.newborn();
+                m[i][from] = m[i][from].add(&unit.one());
+            }
+        }
+        m
+    }
+
+    /// Total population after `days` days, in O(log days) matrix products.
+    fn population<T: Ring>(&self, bins: &[T], days: u64) -> T {
+        let m = power(&self.transition(&bins[0]), days);
+        let zero = bins[0].zero();
+        m.iter()
+            .flat_map(|row| row.iter().zip(bins.iter()).map(|(a, b)| a.mul(b)))
+            .fold(zero, |acc, x| acc.add(&x))
+    }
+}
+
+/// Command line: the species, an optional day count and an optional
+/// modulus for it.
+#[derive(Debug, Eq, PartialEq)]
+struct Options {
+    species: Species,
+    /// Print the population of every day up to `days`.
+    series: bool,
+    days: Option<u64>,
+    modulus: Option<u64>,
 }
 
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            species: Species::LANTERNFISH,
+            series: false,
+            days: None,
+            modulus: None,
+        }
+    }
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, ()> {
+        fn number<T: std::str::FromStr>(arg: Option<&String>) -> Result<T, ()> {
+            arg.ok_or(())?.parse().map_err(|_| ())
+        }
+
+        let mut options = Self::default();
+        let mut positional = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--cycle" => options.species.cycle = number(args.next())?,
+                "--delay" => options.species.delay = number(args.next())?,
+                "--lifespan" => options.species.lifespan = Some(number(args.next())?),
+                "--series" => options.series = true,
+                _ => positional.push(arg),
+            }
+        }
+        let species = options.species;
+        if species.cycle == 0 || species.lifespan == Some(0) {
+            return Err(());
+        }
+
+        match positional[..] {
+            [] if !options.series => (),
+            [days] => options.days = Some(number(Some(days))?),
+            [days, modulus] => {
+                let modulus = number(Some(modulus))?;
+                if modulus == 0 {
+                    return Err(());
+                }
+                options.days = Some(number(Some(days))?);
+                options.modulus = Some(modulus);
+            }
+            _ => return Err(()),
+        }
+        Ok(options)
+    }
+}
+
+/// Prints the population after `days` days, or for every day up to it.
+fn report<T: Ring + fmt::Display>(species: &Species, bins: &[T], days: u64, series: bool) {
+    if series {
+        for (day, population) in species.series(bins, days as usize).iter().enumerate() {
+            println!("{} {}", day, population);
+        }
+    } else {
+        println!("{}", species.population(bins, days));
+    }
+}
+
+/// Usage: day06 [--cycle N] [--delay N] [--lifespan N] [--series] [DAYS [MODULUS]] < input
+///
+/// Without a day count, prints the population after 80 and 256 days. Given
+/// one, prints the population after that many days: modulo the (prime)
+/// modulus if there is one, which works up to 10^18 days, and exactly
+/// otherwise. With `--series`, prints `day population` for every day up to
+/// it instead. The other options change the species, see `Species`.
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = Options::parse(&args).unwrap_or_else(|()| {
+        eprintln!(
+            "usage: day06 [--cycle N] [--delay N] [--lifespan N] [--series] [DAYS [MODULUS]]"
+        );
+        std::process::exit(2);
+    });
+
     let numbers = lines()[0]
         .split(',')
-        .into_iter()
-        .map(|num| num.parse::<Num>().unwrap())
+        .map(|num| num.parse::<usize>().unwrap())
         .collect::<Vec<_>>();
-    assert!(*numbers.iter().min().unwrap() >= 1);
-    assert!(*numbers.iter().max().unwrap() <= 8);
 
-    let mut bins: [Num; 9] = [0; 9];
-    for num in numbers {
-        bins[num] += 1;
+    let species = options.species;
+    if let Some(timer) = numbers.iter().find(|timer| **timer >= species.timers()) {
+        eprintln!("timer {} is out of range", timer);
+        std::process::exit(1);
+    }
+    match options {
+        Options {
+            days: Some(days),
+            modulus: Some(modulus),
+            series,
+            ..
+        } => {
+            let bins = species.bins(&numbers, &Mod::of(0, modulus));
+            report(&species, &bins, days, series);
+        }
+        Options {
+            days: Some(days),
+            modulus: None,
+            series,
+            ..
+        } => {
+            let bins = species.bins(&numbers, &Big::of(0));
+            report(&species, &bins, days, series);
+        }
+        Options { days: None, .. } => {
+            let bins = species.bins(&numbers, &(0 as Num));
+            println!("{}", species.population(&bins, 80));
+            println!("{}", species.population(&bins, 256));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: [usize; 5] = [3, 4, 3, 1, 2];
+
+    #[test]
+    fn test_population() {
+        let species = Species::LANTERNFISH;
+        let bins = species.bins(&EXAMPLE, &(0 as Num));
+        assert_eq!(species.population(&bins, 18), 26);
+        assert_eq!(species.population(&bins, 80), 5934);
+        assert_eq!(species.population(&bins, 256), 26984457539);
+
+        let series = species.series(&bins, 100);
+        for (days, expected) in series.into_iter().enumerate() {
+            assert_eq!(species.population(&bins, days as u64), expected);
+        }
+    }
+
+    #[test]
+    fn test_modulo() {
+        const P: u64 = 1_000_000_007;
+        let species = Species::LANTERNFISH;
+        let bins = species.bins(&EXAMPLE, &Mod::of(0, P));
+        assert_eq!(species.population(&bins, 256), Mod::of(26984457539, P));
+
+        let exact = species.bins(&EXAMPLE, &Big::of(0));
+        let expected = species.population(&exact, 2000);
+        let expected = expected
+            .limbs
+            .iter()
+            .rev()
+            .fold(0u64, |acc, limb| (acc * BASE + *limb as u64) % P);
+        assert_eq!(species.population(&bins, 2000), Mod::of(expected, P));
+
+        // Huge day counts only take a logarithmic number of products.
+        for days in [256, 1_000_000_000_000_000_000] {
+            let expected = Mod::of(recurrence(&EXAMPLE, days, P), P);
+            assert_eq!(species.population(&bins, days), expected, "days={}", days);
+        }
+    }
+
+    /// Lanternfish population after `days` days modulo `p`, computed apart
+    /// from the matrix: the population obeys P(n) = P(n - 7) + P(n - 9), so
+    /// P(days) follows from the first nine days and x^days reduced modulo
+    /// x^9 - x^2 - 1 by repeated squaring.
+    fn recurrence(timers: &[usize], days: u64, p: u64) -> u64 {
+        let species = Species::LANTERNFISH;
+        let bins = species.bins(timers, &(0 as Num));
+        let start = species.series(&bins, 8);
+
+        let mul = |a: &[u64], b: &[u64]| {
+            let mut c = vec![0u64; 17];
+            for (i, a) in a.iter().enumerate() {
+                for (j, b) in b.iter().enumerate() {
+                    c[i + j] = ((c[i + j] as u128 + *a as u128 * *b as u128) % p as u128) as u64;
+                }
+            }
+            for k in (9..17).rev() {
+                // x^k = x^(k - 7) + x^(k - 9)
+                c[k - 7] = (c[k - 7] + c[k]) % p;
+                c[k - 9] = (c[k - 9] + c[k]) % p;
+            }
+            c.truncate(9);
+            c
+        };
+        let mut result = vec![0; 9];
+        result[0] = 1;
+        let mut base = vec![0; 9];
+        base[1] = 1;
+        let mut exp = days;
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = mul(&result, &base);
+            }
+            base = mul(&base, &base);
+            exp >>= 1;
+        }
+        result.iter().zip(start.iter()).fold(0, |acc, (c, n)| {
+            ((acc as u128 + *c as u128 * *n as u128) % p as u128) as u64
+        })
+    }
+
+    #[test]
+    fn test_options() {
+        let args = |s: &str| {
+            s.split_whitespace()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(Options::parse(&[]), Ok(Options::default()));
+        assert_eq!(
+            Options::parse(&args("1000000000000000000 1000000007")),
+            Ok(Options {
+                days: Some(1_000_000_000_000_000_000),
+                modulus: Some(1_000_000_007),
+                ..Options::default()
+            })
+        );
+        assert_eq!(
+            Options::parse(&args("--cycle 10 --series 26 --delay 5 --lifespan 3")),
+            Ok(Options {
+                species: Species {
+                    cycle: 10,
+                    delay: 5,
+                    lifespan: Some(3),
+                },
+                series: true,
+                days: Some(26),
+                modulus: None,
+            })
+        );
+        let bad = [
+            "x",
+            "80 0",
+            "80 7 1",
+            "--series",
+            "--cycle 0 80",
+            "--lifespan 0 80",
+            "--delay",
+            "--delay -1 80",
+        ];
+        for bad in bad {
+            assert_eq!(Options::parse(&args(bad)), Err(()), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_big() {
+        let a = Big::of(999_999_999_999);
+        assert_eq!(a.add(&Big::of(1)).to_string(), "1000000000000");
+        assert_eq!(a.mul(&a).to_string(), "999999999998000000000001");
+        assert_eq!(Big::of(0).to_string(), "0");
+
+        let species = Species::LANTERNFISH;
+        let bins = species.bins(&EXAMPLE, &Big::of(0));
+        assert_eq!(species.population(&bins, 256).to_string(), "26984457539");
+        let len = species.population(&bins, 1000).to_string().len();
+        assert!(len > 20, "len={}", len);
     }
 
-    for _ in 0..80 {
-        day(&mut bins);
+    #[test]
+    fn test_lifespan() {
+        let species = Species {
+            cycle: 2,
+            delay: 0,
+            lifespan: Some(3),
+        };
+        let bins = species.bins(&[0], &(0 as Num));
+        assert_eq!(species.series(&bins, 4), vec![1, 2, 2, 3, 2]);
+        for days in 0..20 {
+            let expected = species.series(&bins, days)[days];
+            assert_eq!(species.population(&bins, days as u64), expected);
+        }
     }
-    println!("{}", bins.iter().sum::<Num>());
 
-    for _ in 0..256 - 80 {
-        day(&mut bins);
+    #[test]
+    fn test_variant() {
+        // Slower breeders with a longer childhood.
+        let species = Species {
+            cycle: 10,
+            delay: 5,
+            lifespan: None,
+        };
+        let bins = species.bins(&[0], &(0 as Num));
+        let series = species.series(&bins, 26);
+        assert_eq!(&series[0..2], &[1, 2]);
+        assert_eq!(series[10], 2);
+        assert_eq!(series[11], 3);
+        assert_eq!(series[16], 4);
+        assert_eq!(series[25], 5);
+        assert_eq!(series[26], 7);
     }
-    println!("{}", bins.iter().sum::<Num>());
 }
@@ -18,27 +18,349 @@ fn cost2(target: Num, from: Num) -> Num {
     (steps + 1) * steps / 2
 }
 
+fn bounds(positions: &[Num]) -> (Num, Num) {
+    let min = *positions.iter().min().unwrap();
+    let max = *positions.iter().max().unwrap();
+    (min, max)
+}
+
+/// Optimal (target, fuel) for linear cost: any median minimizes the sum of
+/// absolute distances.
+fn median(positions: &[Num]) -> (Num, Num) {
+    let mut sorted = positions.to_vec();
+    sorted.sort_unstable();
+    let target = sorted[(sorted.len() - 1) / 2];
+    (target, total_fuel(target, positions, cost1))
+}
+
+/// Optimal (target, fuel) for triangular cost: the optimum is within 1/2
+/// of the mean, so only the two integers around the mean need checking.
+fn mean(positions: &[Num]) -> (Num, Num) {
+    let sum = positions.iter().sum::<Num>();
+    let lo = sum.div_euclid(positions.len() as Num);
+    (lo..=lo + 1)
+        .map(|target| (target, total_fuel(target, positions, cost2)))
+        .min_by_key(|(_, fuel)| *fuel)
+        .unwrap()
+}
+
+/// Optimal (target, fuel) for any convex per-crab cost, by integer ternary
+/// search over the total fuel: O(n log range) cost evaluations.
+fn ternary<F>(positions: &[Num], cost: F) -> (Num, Num)
+where
+    F: Fn(Num, Num) -> Num,
+{
+    let fuel = |target: Num| total_fuel(target, positions, &cost);
+    let (mut lo, mut hi) = bounds(positions);
+    while hi - lo > 2 {
+        let m1 = lo + (hi - lo) / 3;
+        let m2 = hi - (hi - lo) / 3;
+        let (f1, f2) = (fuel(m1), fuel(m2));
+        if f1 < f2 {
+            hi = m2 - 1;
+        } else if f1 > f2 {
+            lo = m1 + 1;
+        } else {
+            // Convexity keeps a minimum between two equal values.
+            lo = m1;
+            hi = m2;
+        }
+    }
+    (lo..=hi)
+        .map(|target| (target, fuel(target)))
+        .min_by_key(|(_, fuel)| *fuel)
+        .unwrap()
+}
+
+/// Optimal (target, fuel) for crabs on a grid, when the cost of a move is
+/// the sum of per-axis costs: then every axis can be optimized on its own
+/// with a 1D solver, e.g. `median` for Manhattan distance or `mean` for
+/// triangular cost per axis.
+fn align<const D: usize, S>(points: &[[Num; D]], solve: S) -> ([Num; D], Num)
+where
+    S: Fn(&[Num]) -> (Num, Num),
+{
+    let mut target = [0; D];
+    let mut fuel = 0;
+    for (i, t) in target.iter_mut().enumerate() {
+        let axis: Vec<Num> = points.iter().map(|p| p[i]).collect();
+        let (x, f) = solve(&axis);
+        *t = x;
+        fuel += f;
+    }
+    (target, fuel)
+}
+
+/// Cost of moving `d` steps as `c[0] d + c[1] d^2 + ...`, which is convex
+/// for non-negative coefficients.
+fn polynomial(coefficients: &[Num]) -> impl Fn(Num, Num) -> Num + '_ {
+    move |target, from| {
+        let d = (target - from).abs();
+        coefficients.iter().rev().fold(0, |acc, c| (acc + c) * d)
+    }
+}
+
+/// What to solve, from the command line.
+#[derive(Debug, Eq, PartialEq)]
+enum Mode {
+    Puzzle,
+    /// Convex polynomial cost with the given coefficients.
+    Cost(Vec<Num>),
+    /// Crabs on a 2D or 3D grid, one point per line.
+    Grid,
+}
+
+impl Mode {
+    fn parse(args: &[String]) -> Result<Self, ()> {
+        match args {
+            [] => Ok(Mode::Puzzle),
+            [mode] if mode == "grid" => Ok(Mode::Grid),
+            [mode, coefficients] if mode == "cost" => coefficients
+                .split(',')
+                .map(|c| c.parse().ok().filter(|c| *c >= 0))
+                .collect::<Option<_>>()
+                .map(Mode::Cost)
+                .ok_or(()),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Points with `D` comma-separated coordinates, one per line.
+fn points<const D: usize>(lines: &[String]) -> Option<Vec<[Num; D]>> {
+    lines
+        .iter()
+        .map(|line| {
+            let point: Vec<Num> = line
+                .split(',')
+                .map(|x| x.parse().ok())
+                .collect::<Option<_>>()?;
+            point.try_into().ok()
+        })
+        .collect()
+}
+
+/// Prints the optimal target and fuel on the grid, for Manhattan distance
+/// and then for triangular cost per axis.
+fn print_grid<const D: usize>(points: &[[Num; D]]) {
+    for (target, fuel) in [align(points, median), align(points, mean)] {
+        let target: Vec<String> = target.iter().map(|x| x.to_string()).collect();
+        println!("{} {}", target.join(","), fuel);
+    }
+}
+
+/// Usage: day07 [cost C1,C2,... | grid] < input
+///
+/// With `cost`, prints the optimal target and fuel when moving `d` steps
+/// costs `C1 d + C2 d^2 + ...` instead. With `grid`, the input has one 2D
+/// or 3D point per line, see `print_grid`.
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mode = Mode::parse(&args).unwrap_or_else(|()| {
+        eprintln!("usage: day07 [cost C1,C2,... | grid]");
+        std::process::exit(2);
+    });
+
+    if mode == Mode::Grid {
+        let lines = lines();
+        if let Some(points) = points::<2>(&lines) {
+            print_grid(&points);
+        } else if let Some(points) = points::<3>(&lines) {
+            print_grid(&points);
+        } else {
+            eprintln!("expected points with 2 or 3 coordinates");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let positions = lines()[0]
         .split(',')
-        .into_iter()
         .map(|num| num.parse::<Num>().unwrap())
         .collect::<Vec<_>>();
 
-    let min = *positions.iter().min().unwrap();
-    let max = *positions.iter().max().unwrap();
+    if let Mode::Cost(coefficients) = mode {
+        let (target, fuel) = ternary(&positions, polynomial(&coefficients));
+        println!("{} {}", target, fuel);
+        return;
+    }
 
-    let (_target, fuel) = (min..=max)
-        .into_iter()
-        .map(|target| (target, total_fuel(target, &positions, cost1)))
-        .min_by_key(|(_, fuel)| *fuel)
-        .unwrap();
+    let (_target, fuel) = median(&positions);
     println!("{}", fuel);
 
-    let (_target, fuel) = (min..=max)
-        .into_iter()
-        .map(|target| (target, total_fuel(target, &positions, cost2)))
-        .min_by_key(|(_, fuel)| *fuel)
-        .unwrap();
+    let (_target, fuel) = mean(&positions);
     println!("{}", fuel);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: [Num; 10] = [16, 1, 2, 0, 4, 2, 7, 1, 2, 14];
+
+    /// Brute-force reference, O(range * n).
+    fn scan<F>(positions: &[Num], cost: F) -> (Num, Num)
+    where
+        F: Fn(Num, Num) -> Num,
+    {
+        let (min, max) = bounds(positions);
+        (min..=max)
+            .map(|target| (target, total_fuel(target, positions, &cost)))
+            .min_by_key(|(_, fuel)| *fuel)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_example() {
+        assert_eq!(median(&EXAMPLE), (2, 37));
+        assert_eq!(mean(&EXAMPLE), (5, 168));
+        assert_eq!(ternary(&EXAMPLE, cost1), (2, 37));
+        assert_eq!(ternary(&EXAMPLE, cost2), (5, 168));
+    }
+
+    #[test]
+    fn test_against_scan() {
+        let cases: [&[Num]; 9] = [
+            &EXAMPLE,
+            &[0],
+            &[5, 5, 5],
+            &[-20, 79],
+            &[0, 1],
+            &[3, -7, 12, 12, 0, 45, -2],
+            &[1, 1, 1, 1, 100],
+            &[-20, -19, 50, 51, 52, 79, 0, 0],
+            &[9, 3, 60, 61, 61, 7, -15, 33, 34, 2, 2, 2, 70, 18],
+        ];
+        let cost3 = polynomial(&[3, 1]);
+
+        for positions in cases {
+            let fuel = |(_, fuel): (Num, Num)| fuel;
+            assert_eq!(fuel(median(positions)), fuel(scan(positions, cost1)));
+            assert_eq!(fuel(mean(positions)), fuel(scan(positions, cost2)));
+            assert_eq!(
+                fuel(ternary(positions, cost1)),
+                fuel(scan(positions, cost1))
+            );
+            assert_eq!(
+                fuel(ternary(positions, cost2)),
+                fuel(scan(positions, cost2))
+            );
+            assert_eq!(
+                fuel(ternary(positions, &cost3)),
+                fuel(scan(positions, &cost3))
+            );
+
+            let (target, fuel) = ternary(positions, &cost3);
+            assert_eq!(fuel, total_fuel(target, positions, &cost3));
+        }
+    }
+
+    #[test]
+    fn test_mode() {
+        let args = |s: &str| {
+            s.split_whitespace()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(Mode::parse(&[]), Ok(Mode::Puzzle));
+        assert_eq!(
+            Mode::parse(&args("cost 3,0,1")),
+            Ok(Mode::Cost(vec![3, 0, 1]))
+        );
+        assert_eq!(Mode::parse(&args("grid")), Ok(Mode::Grid));
+        for bad in [
+            "cost",
+            "cost 1,-1",
+            "cost x",
+            "fuel 1",
+            "cost 1 2",
+            "grid 2",
+        ] {
+            assert_eq!(Mode::parse(&args(bad)), Err(()), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_polynomial() {
+        assert_eq!(polynomial(&[1])(2, 7), 5);
+        assert_eq!(polynomial(&[3, 1])(7, 2), 40);
+        assert_eq!(polynomial(&[0, 0, 2])(-1, 1), 16);
+        assert_eq!(polynomial(&[])(0, 9), 0);
+    }
+
+    fn grid_fuel<const D: usize, F>(target: [Num; D], points: &[[Num; D]], cost: F) -> Num
+    where
+        F: Fn(Num, Num) -> Num,
+    {
+        points
+            .iter()
+            .map(|p| (0..D).map(|i| cost(target[i], p[i])).sum::<Num>())
+            .sum()
+    }
+
+    #[test]
+    fn test_align() {
+        let cases: [&[[Num; 2]]; 6] = [
+            &[[0, 0]],
+            &[[0, 0], [14, 14]],
+            &[[3, 7], [3, 7], [10, 1]],
+            &[[1, 2], [5, 9], [13, 0], [7, 7], [2, 14], [9, 3]],
+            &[[0, 14], [14, 0], [7, 7], [6, 8], [8, 6]],
+            &[[4, 4], [4, 5], [4, 6], [12, 5], [0, 5], [13, 13], [1, 1]],
+        ];
+        for points in cases {
+            let brute = |cost: fn(Num, Num) -> Num| {
+                (0..15)
+                    .flat_map(|x| (0..15).map(move |y| [x, y]))
+                    .map(|t| grid_fuel(t, points, cost))
+                    .min()
+                    .unwrap()
+            };
+
+            let (target, fuel) = align(points, median);
+            assert_eq!(fuel, grid_fuel(target, points, cost1));
+            assert_eq!(fuel, brute(cost1));
+
+            let (target, fuel) = align(points, mean);
+            assert_eq!(fuel, grid_fuel(target, points, cost2));
+            assert_eq!(fuel, brute(cost2));
+        }
+
+        let cases: [&[[Num; 3]]; 4] = [
+            &[[0, 0, 0]],
+            &[[7, 7, 3], [0, 0, -4]],
+            &[[1, 2, -3], [5, 6, 2], [3, 3, 0], [7, 0, 1]],
+            &[[2, 2, 2], [2, 2, 2], [6, 1, -4], [0, 7, 3], [4, 4, -1]],
+        ];
+        for points in cases {
+            let brute = |cost: fn(Num, Num) -> Num| {
+                (0..8)
+                    .flat_map(|x| (0..8).flat_map(move |y| (-4..4).map(move |z| [x, y, z])))
+                    .map(|t| grid_fuel(t, points, cost))
+                    .min()
+                    .unwrap()
+            };
+
+            let (target, fuel) = align(points, median);
+            assert_eq!(fuel, grid_fuel(target, points, cost1));
+            assert_eq!(fuel, brute(cost1));
+
+            let (target, fuel) = align(points, mean);
+            assert_eq!(fuel, grid_fuel(target, points, cost2));
+            assert_eq!(fuel, brute(cost2));
+        }
+    }
+
+    #[test]
+    fn test_points() {
+        let lines = |s: &str| {
+            s.split_whitespace()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(points::<2>(&lines("1,2 -3,4")), Some(vec![[1, 2], [-3, 4]]));
+        assert_eq!(points::<2>(&lines("1,2 3,4,5")), None);
+        assert_eq!(points::<3>(&lines("1,2,3")), Some(vec![[1, 2, 3]]));
+        assert_eq!(points::<3>(&lines("1,x,3")), None);
+    }
+}
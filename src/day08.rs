@@ -1,5 +1,6 @@
-use advent_of_code_2021::util::input;
+use advent_of_code_2021::util::lines;
 use std::collections::HashSet;
+use std::fmt;
 use std::str::FromStr;
 
 type Digit = HashSet<char>;
@@ -12,118 +13,355 @@ struct Observation {
 }
 
 impl FromStr for Observation {
-    type Err = ();
+    type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut it = s.split(" | ");
-        let before = it.next().unwrap();
-        let after = it.next().unwrap();
+        let (before, after) = match (it.next(), it.next(), it.next()) {
+            (Some(before), Some(after), None) => (before, after),
+            _ => return Err(Error::Malformed),
+        };
 
         let patterns = before
             .split_whitespace()
-            .into_iter()
             .map(|chars| chars.chars().collect())
             .collect();
 
         let outputs = after
             .split_whitespace()
-            .into_iter()
             .map(|chars| chars.chars().collect())
-            .collect();
+            .collect::<Vec<_>>();
 
+        if outputs.is_empty() {
+            return Err(Error::Malformed);
+        }
         Ok(Self { patterns, outputs })
     }
 }
 
-const TEN: usize = 10;
+type Mask = u32;
 
-fn overlap(a: &Digit, b: &Digit) -> usize {
-    a.intersection(b).count()
+/// Segment display definition: segment names and, for each glyph, the
+/// symbol it shows and the segments lit for it.
+#[derive(Debug)]
+struct Display {
+    segments: Vec<char>,
+    glyphs: Vec<(char, Mask)>,
 }
 
-fn solve(observation: &Observation) -> Num {
-    let mut mapping: [Digit; TEN] = Default::default();
+impl Display {
+    fn new(segments: &str, glyphs: &[(char, &str)]) -> Self {
+        let segments: Vec<char> = segments.chars().collect();
+        assert!(segments.len() <= Mask::BITS as usize);
+        let mut display = Self {
+            segments,
+            glyphs: Vec::with_capacity(glyphs.len()),
+        };
+        for (symbol, lit) in glyphs {
+            let mask = display.mask(&lit.chars().collect()).unwrap();
+            display.glyphs.push((*symbol, mask));
+        }
+        display
+    }
 
-    mapping[1] = observation
-        .patterns
-        .iter()
-        .find(|digit| digit.len() == 2)
-        .cloned()
-        .unwrap();
-    mapping[4] = observation
-        .patterns
-        .iter()
-        .find(|digit| digit.len() == 4)
-        .cloned()
-        .unwrap();
-    mapping[7] = observation
-        .patterns
-        .iter()
-        .find(|digit| digit.len() == 3)
-        .cloned()
-        .unwrap();
-    mapping[8] = observation
-        .patterns
-        .iter()
-        .find(|digit| digit.len() == 7)
-        .cloned()
-        .unwrap();
+    fn seven() -> Self {
+        Self::new(
+            "abcdefg",
+            &[
+                ('0', "abcefg"),
+                ('1', "cf"),
+                ('2', "acdeg"),
+                ('3', "acdfg"),
+                ('4', "bcdf"),
+                ('5', "abdfg"),
+                ('6', "abdefg"),
+                ('7', "acf"),
+                ('8', "abcdefg"),
+                ('9', "abcdfg"),
+            ],
+        )
+    }
 
-    let pattern235 = observation
-        .patterns
+    /// Wires carry the same names as segments, just scrambled.
+    fn mask(&self, digit: &Digit) -> Option<Mask> {
+        digit.iter().try_fold(0, |mask, c| {
+            let i = self.segments.iter().position(|s| s == c)?;
+            Some(mask | 1 << i)
+        })
+    }
+
+    fn glyph(&self, mask: Mask) -> Option<char> {
+        self.glyphs
+            .iter()
+            .find(|(_, glyph)| *glyph == mask)
+            .map(|(symbol, _)| *symbol)
+    }
+}
+
+/// Wire to segment permutation: `wiring[wire] == segment`.
+type Wiring = Vec<usize>;
+
+fn rewire(wiring: &Wiring, mask: Mask) -> Mask {
+    wiring
         .iter()
-        .filter(|digit| digit.len() == 5)
-        .cloned();
-    let pattern069 = observation
-        .patterns
+        .enumerate()
+        .filter(|(wire, _)| mask & (1 << wire) != 0)
+        .fold(0, |acc, (_, segment)| acc | 1 << segment)
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum Error {
+    /// The line is not `patterns | outputs`.
+    Malformed,
+    /// A wire that the display does not have.
+    UnknownWire(char),
+    /// The same pattern is listed more than once.
+    Duplicate(String),
+    /// All glyphs are listed, but not as many patterns have this length
+    /// as glyphs do, e.g. the length-2 pattern of digit 1 is missing.
+    Length {
+        len: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// All glyphs are listed, but the output is not one of them.
+    Output(String),
+    /// No wiring is consistent with the observation.
+    Contradiction,
+    /// More than one wiring is consistent with the observation.
+    Ambiguous,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Malformed => write!(f, "expected 'patterns | outputs'"),
+            Error::UnknownWire(c) => write!(f, "unknown wire '{}'", c),
+            Error::Duplicate(p) => write!(f, "pattern '{}' is listed twice", p),
+            Error::Length {
+                len,
+                expected,
+                found,
+            } => write!(
+                f,
+                "expected {} pattern(s) of length {}, found {}",
+                expected, len, found
+            ),
+            Error::Output(p) => write!(f, "output '{}' is not among the patterns", p),
+            Error::Contradiction => write!(f, "no wiring matches the patterns"),
+            Error::Ambiguous => write!(f, "more than one wiring matches the patterns"),
+        }
+    }
+}
+
+fn text(digit: &Digit) -> String {
+    let mut chars: Vec<char> = digit.iter().cloned().collect();
+    chars.sort_unstable();
+    chars.into_iter().collect()
+}
+
+/// Cheap consistency checks that explain what is wrong with an observation
+/// before the wiring search would just fail to find anything.
+fn diagnose(display: &Display, observation: &Observation) -> Result<(), Error> {
+    let patterns: Vec<String> = observation.patterns.iter().map(text).collect();
+    let outputs: Vec<String> = observation.outputs.iter().map(text).collect();
+    let mut wires = patterns
         .iter()
-        .filter(|digit| digit.len() == 6)
-        .cloned();
-
-    let one = mapping[1].clone();
-    let four = mapping[4].clone();
-    pattern235
-        .map(
-            |digit| match (overlap(&digit, &one), overlap(&digit, &four)) {
-                (2, _) => (digit, 3),
-                (_, 3) => (digit, 5),
-                (_, 2) => (digit, 2),
-                _ => unreachable!(),
-            },
-        )
-        .for_each(|(digit, number)| {
-            mapping[number] = digit;
-        });
-
-    pattern069
-        .map(
-            |digit| match (overlap(&digit, &one), overlap(&digit, &four)) {
-                (_, 4) => (digit, 9),
-                (1, 3) => (digit, 6),
-                (2, 3) => (digit, 0),
-                _ => unreachable!(),
-            },
-        )
-        .for_each(|(digit, number)| {
-            mapping[number] = digit;
-        });
+        .chain(outputs.iter())
+        .flat_map(|p| p.chars());
+    if let Some(c) = wires.find(|c| !display.segments.contains(c)) {
+        return Err(Error::UnknownWire(c));
+    }
+
+    let mut seen = HashSet::new();
+    if let Some(p) = patterns.iter().find(|p| !seen.insert(*p)) {
+        return Err(Error::Duplicate(p.clone()));
+    }
 
-    observation
-        .outputs
+    if patterns.len() != display.glyphs.len() {
+        return Ok(());
+    }
+    let mut lengths: Vec<usize> = display
+        .glyphs
         .iter()
-        .map(|digit| {
-            mapping
+        .map(|(_, mask)| mask.count_ones() as usize)
+        .collect();
+    lengths.sort_unstable();
+    lengths.dedup();
+    for len in lengths {
+        let expected = display
+            .glyphs
+            .iter()
+            .filter(|(_, mask)| mask.count_ones() as usize == len)
+            .count();
+        let found = patterns.iter().filter(|p| p.len() == len).count();
+        if expected != found {
+            return Err(Error::Length {
+                len,
+                expected,
+                found,
+            });
+        }
+    }
+    if let Some(output) = outputs
+        .into_iter()
+        .find(|output| !patterns.contains(output))
+    {
+        return Err(Error::Output(output));
+    }
+    Ok(())
+}
+
+/// Constraint search for wirings: every pattern is assigned to a distinct
+/// glyph with as many segments, which narrows down the segments each of its
+/// wires (and each wire outside of it) can drive.
+struct Search<'a> {
+    display: &'a Display,
+    patterns: Vec<Mask>,
+    outputs: Vec<Mask>,
+    limit: usize,
+    found: Vec<Wiring>,
+}
+
+impl<'a> Search<'a> {
+    fn assign(&mut self, used: &mut [bool], done: &mut [bool], candidates: &[Mask]) {
+        if self.found.len() >= self.limit {
+            return;
+        }
+        let n = self.display.segments.len();
+        let options = |j: usize, used: &[bool]| -> Vec<usize> {
+            let len = self.patterns[j].count_ones();
+            (0..self.display.glyphs.len())
+                .filter(|k| !used[*k] && self.display.glyphs[*k].1.count_ones() == len)
+                .collect()
+        };
+
+        // Most constrained pattern first.
+        let next = (0..self.patterns.len())
+            .filter(|j| !done[*j])
+            .min_by_key(|j| options(*j, used).len());
+        let j = match next {
+            Some(j) => j,
+            None => {
+                let mut wiring = vec![n; n];
+                self.permute(0, 0, candidates, &mut wiring);
+                return;
+            }
+        };
+
+        for k in options(j, used) {
+            let glyph = self.display.glyphs[k].1;
+            let narrowed: Vec<Mask> = candidates
                 .iter()
                 .enumerate()
-                .find(|(_, d)| d == &digit)
-                .map(|(i, _)| i as Num)
-                .unwrap()
-        })
-        .fold(0, |sum, x| sum * 10 + x)
+                .map(|(wire, mask)| {
+                    if self.patterns[j] & (1 << wire) != 0 {
+                        mask & glyph
+                    } else {
+                        mask & !glyph
+                    }
+                })
+                .collect();
+            if narrowed.contains(&0) {
+                continue;
+            }
+            used[k] = true;
+            done[j] = true;
+            self.assign(used, done, &narrowed);
+            used[k] = false;
+            done[j] = false;
+        }
+    }
+
+    /// Enumerates bijections within the remaining candidates.
+    fn permute(&mut self, wire: usize, taken: Mask, candidates: &[Mask], wiring: &mut Wiring) {
+        if self.found.len() >= self.limit {
+            return;
+        }
+        if wire == candidates.len() {
+            let valid = self
+                .outputs
+                .iter()
+                .all(|output| self.display.glyph(rewire(wiring, *output)).is_some());
+            if valid {
+                self.found.push(wiring.clone());
+            }
+            return;
+        }
+        for segment in 0..candidates.len() {
+            let bit = 1 << segment;
+            if candidates[wire] & bit != 0 && taken & bit == 0 {
+                wiring[wire] = segment;
+                self.permute(wire + 1, taken | bit, candidates, wiring);
+            }
+        }
+    }
+}
+
+/// Up to `limit` wirings consistent with the observation.
+fn wirings(display: &Display, observation: &Observation, limit: usize) -> Vec<Wiring> {
+    let masks = |digits: &[Digit]| -> Option<Vec<Mask>> {
+        digits.iter().map(|digit| display.mask(digit)).collect()
+    };
+    let (mut patterns, outputs) = match (masks(&observation.patterns), masks(&observation.outputs))
+    {
+        (Some(patterns), Some(outputs)) => (patterns, outputs),
+        _ => return vec![],
+    };
+    patterns.sort_unstable();
+    patterns.dedup();
+
+    let n = display.segments.len();
+    let mut search = Search {
+        display,
+        patterns,
+        outputs,
+        limit,
+        found: vec![],
+    };
+    let all: Mask = if n == Mask::BITS as usize {
+        Mask::MAX
+    } else {
+        (1 << n) - 1
+    };
+    let mut used = vec![false; display.glyphs.len()];
+    let mut done = vec![false; search.patterns.len()];
+    search.assign(&mut used, &mut done, &vec![all; n]);
+    search.found
+}
+
+/// Symbols shown by the outputs, if the wiring is uniquely determined.
+fn decode(display: &Display, observation: &Observation) -> Result<String, Error> {
+    diagnose(display, observation)?;
+    let found = wirings(display, observation, 2);
+    match found.as_slice() {
+        [] => Err(Error::Contradiction),
+        [wiring] => Ok(observation
+            .outputs
+            .iter()
+            .map(|digit| {
+                display
+                    .glyph(rewire(wiring, display.mask(digit).unwrap()))
+                    .unwrap()
+            })
+            .collect()),
+        _ => Err(Error::Ambiguous),
+    }
+}
+
+fn solve(observation: &Observation) -> Result<Num, Error> {
+    let digits = decode(&Display::seven(), observation)?;
+    Ok(digits.parse().unwrap())
 }
 
 fn main() {
-    let input: Vec<Observation> = input();
+    let mut input: Vec<(usize, Observation)> = Vec::new();
+    for (i, line) in lines().into_iter().enumerate() {
+        match line.parse() {
+            Ok(obs) => input.push((i + 1, obs)),
+            Err(e) => eprintln!("line {}: {}", i + 1, e),
+        }
+    }
 
     let filter_1478 = |len: &usize| -> bool {
         *len == 2    // 1
@@ -134,12 +372,21 @@ fn main() {
 
     let part1 = input
         .iter()
-        .flat_map(|obs| obs.outputs.iter().map(|digit| digit.len()))
+        .flat_map(|(_, obs)| obs.outputs.iter().map(|digit| digit.len()))
         .filter(filter_1478)
         .count();
     println!("{}", part1);
 
-    let part2 = input.iter().map(solve).sum::<Num>();
+    let part2 = input
+        .iter()
+        .filter_map(|(line, obs)| match solve(obs) {
+            Ok(n) => Some(n),
+            Err(e) => {
+                eprintln!("line {}: {}", line, e);
+                None
+            }
+        })
+        .sum::<Num>();
     println!("{}", part2);
 }
 
@@ -181,6 +428,89 @@ mod day08_tests {
 
     #[test]
     fn test_solve() {
-        assert_eq!(solve(&observation()), 5353);
+        assert_eq!(solve(&observation()), Ok(5353));
+    }
+
+    fn parse(line: &str) -> Observation {
+        line.parse().unwrap()
+    }
+
+    #[test]
+    fn test_wirings() {
+        let display = Display::seven();
+        let found = wirings(&display, &observation(), 10);
+        assert_eq!(found.len(), 1);
+        // a -> c, b -> f, ... as in the puzzle description.
+        let expected: Wiring = vec![2, 5, 6, 0, 1, 3, 4];
+        assert_eq!(found[0], expected);
+    }
+
+    #[test]
+    fn test_ambiguous() {
+        // Only 1 and 7 are seen: the remaining wires can go anywhere.
+        let obs = parse("ab dab | ab dab");
+        let display = Display::seven();
+        assert_eq!(decode(&display, &obs), Err(Error::Ambiguous));
+        assert!(wirings(&display, &obs, 1000).len() > 1);
+    }
+
+    #[test]
+    fn test_contradiction() {
+        // Two different patterns of length 2, but only one glyph has 2 segments.
+        let obs = parse("ab cd | ab");
+        assert_eq!(decode(&Display::seven(), &obs), Err(Error::Contradiction));
+        // Unknown wire name.
+        let obs = parse("xy | xy");
+        assert_eq!(
+            decode(&Display::seven(), &obs),
+            Err(Error::UnknownWire('x'))
+        );
+    }
+
+    #[test]
+    fn test_custom_display() {
+        // Toy display: a stem, a stem with a foot, and a full frame.
+        let display = Display::new("abc", &[('I', "a"), ('L', "ab"), ('T', "abc")]);
+        let obs = parse("bca cb c | c bc abc");
+        assert_eq!(decode(&display, &obs), Ok("ILT".to_string()));
+        assert_eq!(wirings(&display, &obs, 10), vec![vec![2, 1, 0]]);
+    }
+
+    #[test]
+    fn test_diagnose() {
+        let cases = vec![
+            ("acedgfb cdfbe | cdfeb | ab", Error::Malformed),
+            ("acedgfb cdfbe", Error::Malformed),
+            ("acedgfb cdfbe |", Error::Malformed),
+            (
+                "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb abx | ab",
+                Error::UnknownWire('x'),
+            ),
+            (
+                "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab ba | ab",
+                Error::Duplicate("ab".to_string()),
+            ),
+            (
+                "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb abc | ab",
+                Error::Length {
+                    len: 2,
+                    expected: 1,
+                    found: 0,
+                },
+            ),
+            (
+                "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | abc",
+                Error::Output("abc".to_string()),
+            ),
+            (
+                "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedf ab | ab",
+                Error::Contradiction,
+            ),
+        ];
+
+        for (line, expected) in cases {
+            let actual = line.parse::<Observation>().and_then(|obs| solve(&obs));
+            assert_eq!(actual, Err(expected), "{}", line);
+        }
     }
 }
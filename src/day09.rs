@@ -1,5 +1,5 @@
 use advent_of_code_2021::util::lines;
-use std::collections::{HashSet, VecDeque};
+use std::collections::HashMap;
 
 type Grid = Vec<Vec<u8>>;
 
@@ -21,35 +21,307 @@ fn adj(grid: &[Vec<u8>], row: usize, col: usize) -> Vec<(usize, usize)> {
     adj
 }
 
-fn basin(grid: &[Vec<u8>], row: usize, col: usize) -> usize {
-    let mut seen = HashSet::new();
-    let mut frontier = VecDeque::new();
-    frontier.push_back((row, col));
-    seen.insert((row, col));
-    while !frontier.is_empty() {
-        let (row, col) = frontier.pop_front().unwrap();
-        seen.insert((row, col));
-        let this = grid[row][col];
-        for (i, j) in adj(grid, row, col) {
-            let next = grid[i][j];
-            if !seen.contains(&(i, j)) && next < 9 && this < next {
-                frontier.push_back((i, j));
+/// Disjoint sets over cells indexed by `row * cols + col`.
+struct UnionFind {
+    parent: Vec<usize>,
+    size: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(n: usize) -> Self {
+        Self {
+            parent: (0..n).collect(),
+            size: vec![1; n],
+        }
+    }
+
+    fn find(&mut self, mut x: usize) -> usize {
+        while self.parent[x] != x {
+            self.parent[x] = self.parent[self.parent[x]];
+            x = self.parent[x];
+        }
+        x
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (mut a, mut b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        if self.size[a] < self.size[b] {
+            std::mem::swap(&mut a, &mut b);
+        }
+        self.parent[b] = a;
+        self.size[a] += self.size[b];
+    }
+}
+
+/// Basin segmentation: every cell below 9 carries the id of its basin,
+/// ids are given in reading order of the first cell of each basin.
+struct Basins {
+    labels: Vec<Vec<Option<usize>>>,
+    sizes: Vec<usize>,
+}
+
+impl Basins {
+    fn of(grid: &[Vec<u8>]) -> Self {
+        let (rows, cols) = (grid.len(), grid[0].len());
+        let mut sets = UnionFind::new(rows * cols);
+        for row in 0..rows {
+            for col in 0..cols {
+                if grid[row][col] == 9 {
+                    continue;
+                }
+                for (i, j) in adj(grid, row, col) {
+                    if grid[i][j] < 9 {
+                        sets.union(row * cols + col, i * cols + j);
+                    }
+                }
+            }
+        }
+
+        let mut ids: HashMap<usize, usize> = HashMap::new();
+        let mut sizes = Vec::new();
+        let labels = (0..rows)
+            .map(|row| {
+                (0..cols)
+                    .map(|col| {
+                        if grid[row][col] == 9 {
+                            return None;
+                        }
+                        let root = sets.find(row * cols + col);
+                        let id = *ids.entry(root).or_insert_with(|| {
+                            sizes.push(0);
+                            sizes.len() - 1
+                        });
+                        sizes[id] += 1;
+                        Some(id)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self { labels, sizes }
+    }
+
+    /// One letter per basin (cycling through the alphabet), `#` for 9s.
+    /// With `colour`, basins are also painted with ANSI 256-colour codes.
+    fn render(&self, colour: bool) -> String {
+        const LETTERS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+        self.labels
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|label| match label {
+                        None => "#".to_string(),
+                        Some(id) => {
+                            let c = LETTERS[id % LETTERS.len()] as char;
+                            if colour {
+                                format!("\x1b[38;5;{}m{}\x1b[0m", 1 + id % 230, c)
+                            } else {
+                                c.to_string()
+                            }
+                        }
+                    })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+type Cell = (usize, usize);
+
+/// Lowest pass between two drainage basins.
+#[derive(Debug, Eq, PartialEq)]
+struct Saddle {
+    sinks: (Cell, Cell),
+    cell: Cell,
+    height: u8,
+}
+
+/// Drainage model: every cell flows to its lowest neighbour if that one is
+/// strictly lower (the first one in `adj` order on ties), otherwise it is a
+/// sink. Cells of a flat area are therefore sinks of their own.
+struct Drainage {
+    heights: Grid,
+    flow: Vec<Vec<Option<Cell>>>,
+    sink: Vec<Vec<Cell>>,
+    area: Vec<Vec<usize>>,
+}
+
+impl Drainage {
+    fn of(grid: &[Vec<u8>]) -> Self {
+        let (rows, cols) = (grid.len(), grid[0].len());
+        let flow: Vec<Vec<Option<Cell>>> = (0..rows)
+            .map(|row| {
+                (0..cols)
+                    .map(|col| {
+                        adj(grid, row, col)
+                            .into_iter()
+                            .min_by_key(|(i, j)| grid[*i][*j])
+                            .filter(|(i, j)| grid[*i][*j] < grid[row][col])
+                    })
+                    .collect()
+            })
+            .collect();
+
+        // Downstream cells are strictly lower, so going from the highest
+        // cells down passes all the upstream area before it is forwarded.
+        let mut order: Vec<Cell> = (0..rows)
+            .flat_map(|row| (0..cols).map(move |col| (row, col)))
+            .collect();
+        order.sort_by_key(|(row, col)| std::cmp::Reverse(grid[*row][*col]));
+        let mut area = vec![vec![1; cols]; rows];
+        for (row, col) in order.iter() {
+            if let Some((i, j)) = flow[*row][*col] {
+                area[i][j] += area[*row][*col];
+            }
+        }
+
+        let mut sink = vec![vec![(0, 0); cols]; rows];
+        for (row, col) in order.iter().rev() {
+            sink[*row][*col] = match flow[*row][*col] {
+                Some((i, j)) => sink[i][j],
+                None => (*row, *col),
+            };
+        }
+
+        Self {
+            heights: grid.to_vec(),
+            flow,
+            sink,
+            area,
+        }
+    }
+
+    fn sinks(&self) -> Vec<Cell> {
+        let mut sinks: Vec<Cell> = self
+            .sink
+            .iter()
+            .enumerate()
+            .flat_map(|(row, cells)| {
+                cells
+                    .iter()
+                    .enumerate()
+                    .filter(move |(col, sink)| **sink == (row, *col))
+                    .map(move |(col, _)| (row, col))
+            })
+            .collect();
+        sinks.sort_unstable();
+        sinks
+    }
+
+    /// For every pair of neighbouring drainage basins, the cell where water
+    /// would first spill over from one into the other: the lowest of the
+    /// higher cells over all pairs of adjacent cells across the border.
+    fn saddles(&self) -> Vec<Saddle> {
+        let grid = &self.heights;
+        let mut best: HashMap<(Cell, Cell), Saddle> = HashMap::new();
+        for (row, cells) in grid.iter().enumerate() {
+            for (col, this) in cells.iter().enumerate() {
+                for (i, j) in adj(grid, row, col) {
+                    let (a, b) = (self.sink[row][col], self.sink[i][j]);
+                    if a >= b {
+                        continue;
+                    }
+                    let (cell, height) = if grid[i][j] > *this {
+                        ((i, j), grid[i][j])
+                    } else {
+                        ((row, col), *this)
+                    };
+                    let saddle = best.entry((a, b)).or_insert(Saddle {
+                        sinks: (a, b),
+                        cell,
+                        height,
+                    });
+                    if height < saddle.height {
+                        saddle.cell = cell;
+                        saddle.height = height;
+                    }
+                }
             }
         }
+        let mut saddles: Vec<Saddle> = best.into_values().collect();
+        saddles.sort_by_key(|saddle| saddle.sinks);
+        saddles
+    }
+
+    /// Direction each cell flows in, `o` for sinks.
+    fn arrows(&self) -> String {
+        self.flow
+            .iter()
+            .enumerate()
+            .map(|(row, cells)| {
+                cells
+                    .iter()
+                    .enumerate()
+                    .map(|(col, flow)| match flow {
+                        None => 'o',
+                        Some((i, _)) if *i < row => '^',
+                        Some((i, _)) if *i > row => 'v',
+                        Some((_, j)) if *j < col => '<',
+                        Some(_) => '>',
+                    })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
     }
-    seen.len()
 }
 
+fn parse(lines: &[String]) -> Grid {
+    lines
+        .iter()
+        .map(|line| line.chars().map(|c| c as u8 - b'0').collect())
+        .collect()
+}
+
+/// Usage: day09 [basins [--colour] | drainage] < input
+///
+/// With `basins`, prints the basin map instead. With `drainage`, prints the
+/// flow map, then every sink with the area that drains into it and every
+/// saddle between two drainage basins.
 fn main() {
-    let grid: Grid = lines()
-        .into_iter()
-        .map(|line| line.chars().into_iter().map(|c| c as u8 - b'0').collect())
-        .collect();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+    if !matches!(
+        args[..],
+        [] | ["basins"] | ["basins", "--colour"] | ["drainage"]
+    ) {
+        eprintln!("usage: day09 [basins [--colour] | drainage]");
+        std::process::exit(2);
+    }
+
+    let grid: Grid = parse(&lines());
     let (rows, cols) = (grid.len(), grid[0].len());
 
+    if args.first() == Some(&"basins") {
+        println!("{}", Basins::of(&grid).render(args.len() > 1));
+        return;
+    }
+    if args == ["drainage"] {
+        let drainage = Drainage::of(&grid);
+        println!("{}", drainage.arrows());
+        for (row, col) in drainage.sinks() {
+            println!("sink {},{} area {}", row, col, drainage.area[row][col]);
+        }
+        for Saddle {
+            sinks: (a, b),
+            cell,
+            height,
+        } in drainage.saddles()
+        {
+            println!(
+                "saddle {},{} height {} between {},{} and {},{}",
+                cell.0, cell.1, height, a.0, a.1, b.0, b.1
+            );
+        }
+        return;
+    }
+
     let minimums = (0..rows)
-        .into_iter()
-        .flat_map(|row| (0..cols).into_iter().map(move |col| (row, col)))
+        .flat_map(|row| (0..cols).map(move |col| (row, col)))
         .filter(|(row, col)| {
             let cell = grid[*row][*col];
             adj(&grid, *row, *col)
@@ -64,12 +336,88 @@ fn main() {
         .sum::<usize>();
     println!("{}", part1);
 
-    let mut basin_sizes = minimums
-        .iter()
-        .map(|(row, col)| basin(&grid, *row, *col))
-        .collect::<Vec<_>>();
+    let mut basin_sizes = Basins::of(&grid).sizes;
     basin_sizes.sort_unstable();
 
     let part2 = basin_sizes.iter().rev().take(3).product::<usize>();
     println!("{}", part2);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(s: &str) -> Grid {
+        let lines: Vec<String> = s.split_whitespace().map(|l| l.to_string()).collect();
+        parse(&lines)
+    }
+
+    const EXAMPLE: &str = "2199943210 3987894921 9856789892 8767896789 9899965678";
+
+    #[test]
+    fn test_basins() {
+        let basins = Basins::of(&grid(EXAMPLE));
+        assert_eq!(basins.sizes, vec![3, 9, 14, 9]);
+        let expected = "\
+aa###bbbbb
+a#ccc#b#bb
+#ccccc#d#b
+ccccc#ddd#
+#c###ddddd";
+        assert_eq!(basins.render(false), expected);
+        assert!(basins.render(true).contains("\x1b[38;5;1ma"));
+    }
+
+    #[test]
+    fn test_plateau() {
+        // Equal heights next to each other belong to the same basin.
+        let basins = Basins::of(&grid("5559 5595 9955"));
+        assert_eq!(basins.sizes, vec![5, 3]);
+        assert_eq!(basins.labels[0][0], Some(0));
+        assert_eq!(basins.labels[2][3], Some(1));
+        assert_eq!(basins.labels[0][3], None);
+    }
+
+    #[test]
+    fn test_drainage() {
+        let grid = grid(EXAMPLE);
+        let drainage = Drainage::of(&grid);
+        let sinks = drainage.sinks();
+        assert_eq!(sinks, vec![(0, 1), (0, 9), (2, 2), (4, 6)]);
+        let total = sinks
+            .iter()
+            .map(|(i, j)| drainage.area[*i][*j])
+            .sum::<usize>();
+        assert_eq!(total, 50);
+
+        assert_eq!(drainage.flow[0][0], Some((0, 1)));
+        assert_eq!(drainage.sink[1][2], (2, 2));
+        assert_eq!(drainage.area[0][0], 3);
+
+        let expected = "\
+>o<v>>>>>o
+^^vvv^^^^^
+^>o<<<^v^^
+>>^^^vvvv^
+^^^^>>o<<<";
+        assert_eq!(drainage.arrows(), expected);
+    }
+
+    #[test]
+    fn test_saddles() {
+        // Two valleys separated by a ridge with a pass of height 5.
+        let grid = grid("12521 23632");
+        let drainage = Drainage::of(&grid);
+        assert_eq!(drainage.sinks(), vec![(0, 0), (0, 4)]);
+        assert_eq!(drainage.area[0][0], 6);
+        assert_eq!(drainage.area[0][4], 4);
+        assert_eq!(
+            drainage.saddles(),
+            vec![Saddle {
+                sinks: ((0, 0), (0, 4)),
+                cell: (0, 2),
+                height: 5,
+            }]
+        );
+    }
+}
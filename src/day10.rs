@@ -1,101 +1,986 @@
 use advent_of_code_2021::util::lines;
+use repair::repair;
+use std::io::{self, Read};
 
-fn cost1(chr: &char) -> usize {
-    match chr {
-        ')' => 3,
-        ']' => 57,
-        '}' => 1197,
-        '>' => 25137,
+fn cost1(closer: &str) -> usize {
+    match closer {
+        ")" => 3,
+        "]" => 57,
+        "}" => 1197,
+        ">" => 25137,
         _ => unreachable!(),
     }
 }
 
-fn cost2(chr: &char) -> usize {
-    match chr {
-        ')' => 1,
-        ']' => 2,
-        '}' => 3,
-        '>' => 4,
+fn cost2(closer: &str) -> usize {
+    match closer {
+        ")" => 1,
+        "]" => 2,
+        "}" => 3,
+        ">" => 4,
         _ => unreachable!(),
     }
 }
 
-fn score(seq: &[char]) -> usize {
-    seq.iter().fold(0usize, |sum, c| sum * 5 + cost2(c))
+/// Completion score of the closers, or `None` if it overflows.
+fn score<'a>(closers: impl IntoIterator<Item = &'a str>) -> Option<usize> {
+    closers
+        .into_iter()
+        .try_fold(0usize, |sum, c| sum.checked_mul(5)?.checked_add(cost2(c)))
 }
 
-fn mirror(chr: &char) -> char {
-    match chr {
-        '(' => ')',
-        '[' => ']',
-        '{' => '}',
-        '<' => '>',
-        ')' => '(',
-        ']' => '[',
-        '}' => '{',
-        '>' => '<',
-        _ => unreachable!(),
-    }
+/// Delimiter pair, e.g. `(` and `)` or `begin` and `end`. A quote is a pair
+/// where nothing in between is checked, except for `\` escaping the next char.
+#[derive(Debug, Clone)]
+struct Pair {
+    open: String,
+    close: String,
+    quote: bool,
 }
 
-fn opens(chr: &char) -> bool {
-    matches!(chr, '(' | '[' | '{' | '<')
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Token {
+    Open(usize),
+    Close(usize),
 }
 
-fn closes(chr: &char) -> bool {
-    opens(&mirror(chr))
+#[derive(Debug, Default)]
+struct Delimiters {
+    pairs: Vec<Pair>,
+}
+
+impl Delimiters {
+    fn brackets() -> Self {
+        Self::default()
+            .pair("(", ")")
+            .pair("[", "]")
+            .pair("{", "}")
+            .pair("<", ">")
+    }
+
+    fn pair(mut self, open: &str, close: &str) -> Self {
+        self.pairs.push(Pair {
+            open: open.to_string(),
+            close: close.to_string(),
+            quote: false,
+        });
+        self
+    }
+
+    fn quote(mut self, open: &str, close: &str) -> Self {
+        self.pairs.push(Pair {
+            open: open.to_string(),
+            close: close.to_string(),
+            quote: true,
+        });
+        self
+    }
+
+    /// Longest delimiter starting at byte `at`. Word-like delimiters only
+    /// match whole words, so that `end` is not found in `blend`.
+    fn token(&self, text: &str, at: usize) -> Option<(Token, usize)> {
+        let rest = &text[at..];
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        let bounded = |len: usize| {
+            let before = text[..at].chars().next_back().is_some_and(is_word);
+            let after = rest[len..].chars().next().is_some_and(is_word);
+            !before && !after
+        };
+        let matches =
+            |s: &str| rest.starts_with(s) && (!s.chars().any(is_word) || bounded(s.len()));
+
+        let mut best: Option<(Token, usize)> = None;
+        for (i, pair) in self.pairs.iter().enumerate() {
+            for (token, s) in [(Token::Open(i), &pair.open), (Token::Close(i), &pair.close)] {
+                if matches(s) && best.is_none_or(|(_, len)| s.len() > len) {
+                    best = Some((token, s.len()));
+                }
+            }
+        }
+        best
+    }
+
+    /// Token (if any) at byte `at` and its length, given the quote the text
+    /// is in. Inside a quote only its own closer matters, and `\` escapes
+    /// the next char.
+    fn lex(&self, text: &str, at: usize, quote: Option<usize>) -> (Option<Token>, usize) {
+        let rest = &text[at..];
+        let char_len = |s: &str| s.chars().next().map_or(0, |c| c.len_utf8());
+        match quote.map(|i| (i, &self.pairs[i])) {
+            Some((i, pair)) => {
+                if rest.starts_with(&pair.close) {
+                    (Some(Token::Close(i)), pair.close.len())
+                } else if let Some(escaped) = rest.strip_prefix('\\') {
+                    (None, 1 + char_len(escaped))
+                } else {
+                    (None, char_len(rest))
+                }
+            }
+            None => match self.token(text, at) {
+                Some((token, len)) => (Some(token), len),
+                None => (None, char_len(rest)),
+            },
+        }
+    }
 }
 
+#[derive(Debug, Eq, PartialEq)]
 enum Check {
-    Corrupted(char),
-    Incomplete(Vec<char>),
+    /// Position (1-based line and column in chars) of an unexpected closer,
+    /// and the closer that was expected there, if any.
+    Corrupted {
+        line: usize,
+        column: usize,
+        expected: Option<String>,
+        found: String,
+    },
+    /// Pairs whose closers are still needed, innermost first.
+    Incomplete(Vec<usize>),
     Ok,
 }
 
-fn check(line: &str) -> Check {
-    let mut stack = Vec::new();
+/// Checker state that can be fed text piece by piece.
+struct Checker<'a> {
+    delimiters: &'a Delimiters,
+    stack: Vec<usize>,
+    line: usize,
+    column: usize,
+}
 
-    for c in line.chars() {
-        if opens(&c) {
-            stack.push(mirror(&c));
-        } else if closes(&c) {
-            if stack.is_empty() {
-                return Check::Corrupted(c);
+impl<'a> Checker<'a> {
+    fn new(delimiters: &'a Delimiters) -> Self {
+        Self {
+            delimiters,
+            stack: Vec::new(),
+            line: 1,
+            column: 1,
+        }
+    }
+
+    /// Consumes the token (or char) at byte `at` of `text`, returning its
+    /// length in bytes, or the corruption found there. The text must extend
+    /// far enough past `at` for the longest delimiter to be recognized.
+    fn step(&mut self, text: &str, at: usize) -> Result<usize, Check> {
+        let delimiters = self.delimiters;
+        let top = self.stack.last().cloned();
+        let quote = top.filter(|i| delimiters.pairs[*i].quote);
+
+        let (token, len) = delimiters.lex(text, at, quote);
+        match token {
+            Some(Token::Open(i)) => self.stack.push(i),
+            Some(Token::Close(i)) => {
+                if top != Some(i) {
+                    return Err(Check::Corrupted {
+                        line: self.line,
+                        column: self.column,
+                        expected: top.map(|i| delimiters.pairs[i].close.clone()),
+                        found: delimiters.pairs[i].close.clone(),
+                    });
+                }
+                self.stack.pop();
             }
-            if stack.pop().unwrap() != c {
-                return Check::Corrupted(c);
+            None => (),
+        }
+
+        for c in text[at..at + len].chars() {
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
             }
         }
+        Ok(len)
     }
 
-    if stack.is_empty() {
-        Check::Ok
-    } else {
-        Check::Incomplete(stack.into_iter().rev().collect())
+    fn finish(mut self) -> Check {
+        if self.stack.is_empty() {
+            Check::Ok
+        } else {
+            self.stack.reverse();
+            Check::Incomplete(self.stack)
+        }
     }
 }
 
-fn main() {
-    let input = lines();
+fn check(delimiters: &Delimiters, text: &str) -> Check {
+    let mut checker = Checker::new(delimiters);
+    let mut at = 0;
+    while at < text.len() {
+        match checker.step(text, at) {
+            Ok(len) => at += len,
+            Err(check) => return check,
+        }
+    }
+    checker.finish()
+}
 
-    let part1 = input
+/// Checks each line of the input as it is read in chunks of `chunk` bytes,
+/// calling `f` with the 1-based line number and the result, whose position
+/// is within the whole input. Invalid UTF-8 is an `InvalidData` error. Only a chunk,
+/// a few bytes of lookahead and the delimiter stack of the current line are
+/// kept, so lines may be arbitrarily long.
+fn stream<R, F>(delimiters: &Delimiters, mut reader: R, chunk: usize, mut f: F) -> io::Result<()>
+where
+    R: Read,
+    F: FnMut(usize, Check),
+{
+    // Longest delimiter plus a char for the word boundary and escapes.
+    let lookahead = delimiters
+        .pairs
         .iter()
-        .filter_map(|line| match check(line) {
-            Check::Corrupted(c) => Some(cost1(&c)),
-            _ => None,
-        })
-        .sum::<usize>();
+        .map(|pair| pair.open.len().max(pair.close.len()))
+        .max()
+        .unwrap_or_default()
+        + 8;
+
+    let mut buf = vec![0u8; chunk.max(1)];
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut pending = String::new();
+    // Byte offset in `pending` of the next char to check.
+    let mut at = 0;
+    let mut number = 1;
+    let mut checker = Checker::new(delimiters);
+    let mut failed: Option<Check> = None;
+
+    loop {
+        let n = reader.read(&mut buf)?;
+        let eof = n == 0;
+        bytes.extend_from_slice(&buf[..n]);
+        let (valid, invalid) = match std::str::from_utf8(&bytes) {
+            Ok(text) => (text.len(), false),
+            // A char cut at the end of the chunk is completed by the next one.
+            Err(e) => (e.valid_up_to(), e.error_len().is_some() || eof),
+        };
+        pending.push_str(std::str::from_utf8(&bytes[..valid]).unwrap());
+        bytes.drain(..valid);
+
+        loop {
+            let newline = pending[at..].find('\n').map(|i| at + i);
+            let end = newline.unwrap_or(pending.len());
+            let limit = if newline.is_some() || eof {
+                end
+            } else {
+                end.saturating_sub(lookahead)
+            };
+            let text = &pending[..end];
+            while failed.is_none() && at < limit {
+                match checker.step(text, at) {
+                    Ok(len) => at += len,
+                    Err(check) => failed = Some(check),
+                }
+            }
+
+            match newline {
+                Some(i) => {
+                    number += 1;
+                    let next = Checker {
+                        line: number,
+                        ..Checker::new(delimiters)
+                    };
+                    let done = std::mem::replace(&mut checker, next);
+                    f(number - 1, failed.take().unwrap_or_else(|| done.finish()));
+                    pending.drain(..=i);
+                    at = 0;
+                }
+                None => {
+                    if failed.is_some() {
+                        at = pending.len();
+                    }
+                    // Keep the last consumed char for word boundary checks.
+                    let keep = pending[..at]
+                        .chars()
+                        .next_back()
+                        .map_or(0, |c| c.len_utf8());
+                    pending.drain(..at - keep);
+                    at = keep;
+                    break;
+                }
+            }
+        }
+
+        // Lines before the invalid bytes have been checked by now.
+        if invalid {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: invalid UTF-8", number),
+            ));
+        }
+        if eof {
+            if !pending.is_empty() {
+                f(number, failed.take().unwrap_or_else(|| checker.finish()));
+            }
+            return Ok(());
+        }
+    }
+}
+
+/// Median of the scores, by selection instead of sorting.
+fn median(scores: &mut [usize]) -> Option<usize> {
+    if scores.is_empty() {
+        return None;
+    }
+    let mid = scores.len() / 2;
+    Some(*scores.select_nth_unstable(mid).1)
+}
+
+/// Repairing corrupted lines.
+mod repair {
+    use super::{check, Check, Delimiters, Token};
+
+    /// Single edit, at a 1-based char column of the original line. Insertions
+    /// go right before the char at that column (or at the end of the line).
+    #[derive(Debug, Clone, Eq, PartialEq)]
+    pub(super) enum Edit {
+        Insert {
+            column: usize,
+            text: String,
+        },
+        Delete {
+            column: usize,
+            text: String,
+        },
+        Substitute {
+            column: usize,
+            from: String,
+            to: String,
+        },
+    }
+
+    impl Edit {
+        fn column(&self) -> usize {
+            match self {
+                Edit::Insert { column, .. } => *column,
+                Edit::Delete { column, .. } => *column,
+                Edit::Substitute { column, .. } => *column,
+            }
+        }
+    }
+
+    #[derive(Debug, Eq, PartialEq)]
+    pub(super) struct Repair {
+        pub(super) edits: Vec<Edit>,
+        /// The balanced line.
+        pub(super) line: String,
+        /// The original line with edits marked up as `[-deleted-]{+inserted+}`.
+        pub(super) marked: String,
+    }
+
+    /// Delimiter found in a line: pair index, whether it opens, char column and length.
+    #[derive(Debug, Clone, Copy)]
+    struct Found {
+        pair: usize,
+        open: bool,
+        column: usize,
+        len: usize,
+    }
+
+    /// Minimal number of insertions, deletions and substitutions that balance
+    /// the line, by interval DP over its delimiters in O(n^3). An unmatched
+    /// opener gets its closer inserted, as line completion does, while an
+    /// unmatched closer is deleted as a stray one.
+    pub(super) fn repair(delimiters: &Delimiters, line: &str) -> Repair {
+        let chars = line.chars().count();
+        let done = |edits: Vec<Edit>| {
+            let (line, marked) = apply(line, &edits);
+            Repair {
+                edits,
+                line,
+                marked,
+            }
+        };
+        if check(delimiters, line) == Check::Ok {
+            return done(vec![]);
+        }
+
+        // Delimiters outside quotes, lexed as `check` does. Quoted text is left
+        // alone: a stray quote closer is deleted and an unterminated quote gets
+        // closed at the end of the line.
+        let mut found: Vec<Found> = Vec::new();
+        let mut edits = Vec::new();
+        let mut quote = None;
+        let (mut at, mut column) = (0, 1);
+        while at < line.len() {
+            let (token, len) = delimiters.lex(line, at, quote);
+            let width = line[at..at + len].chars().count();
+            match token {
+                Some(Token::Open(i)) if delimiters.pairs[i].quote => quote = Some(i),
+                Some(Token::Close(i)) if delimiters.pairs[i].quote => {
+                    if quote == Some(i) {
+                        quote = None;
+                    } else {
+                        edits.push(Edit::Delete {
+                            column,
+                            text: line[at..at + len].to_string(),
+                        });
+                    }
+                }
+                Some(Token::Open(pair)) | Some(Token::Close(pair)) => found.push(Found {
+                    pair,
+                    open: matches!(token, Some(Token::Open(_))),
+                    column,
+                    len: width,
+                }),
+                None => (),
+            }
+            at += len;
+            column += width;
+        }
+        if let Some(i) = quote {
+            edits.push(Edit::Insert {
+                column: chars + 1,
+                text: delimiters.pairs[i].close.clone(),
+            });
+        }
+
+        let n = found.len();
+        // Cost to make `a` and `b` a matching pair by substituting them.
+        let cost = |a: &Found, b: &Found| match (a.open, b.open) {
+            (true, false) if a.pair == b.pair => 0,
+            (true, _) | (false, false) => 1,
+            (false, true) => 2,
+        };
+        // dp[i][j]: cost of balancing found[i..j]; choice k > 0 means pairing i
+        // with k, otherwise i is left unmatched and gets fixed on its own.
+        let mut dp = vec![vec![0usize; n + 1]; n + 1];
+        let mut choice = vec![vec![0usize; n + 1]; n + 1];
+        for len in 1..=n {
+            for i in 0..=n - len {
+                let j = i + len;
+                dp[i][j] = dp[i + 1][j] + 1;
+                for k in i + 1..j {
+                    let c = cost(&found[i], &found[k]) + dp[i + 1][k] + dp[k + 1][j];
+                    if c < dp[i][j] {
+                        dp[i][j] = c;
+                        choice[i][j] = k;
+                    }
+                }
+            }
+        }
+
+        let text = |f: &Found, open: bool| {
+            let pair = &delimiters.pairs[f.pair];
+            if open {
+                pair.open.clone()
+            } else {
+                pair.close.clone()
+            }
+        };
+        let original = |f: &Found| {
+            line.chars()
+                .skip(f.column - 1)
+                .take(f.len)
+                .collect::<String>()
+        };
+        let end = |j: usize| found.get(j).map_or(chars + 1, |f| f.column);
+        // Words put in place of `len` chars at `column` must not glue to
+        // neighbouring words.
+        let is_word = |c: &char| c.is_alphanumeric() || *c == '_';
+        let pad = |column: usize, len: usize, text: String| {
+            if !text.chars().any(|c| is_word(&c)) {
+                return text;
+            }
+            let before = column > 1 && line.chars().nth(column - 2).is_some_and(|c| is_word(&c));
+            let after = line
+                .chars()
+                .nth(column - 1 + len)
+                .is_some_and(|c| is_word(&c));
+            format!(
+                "{}{}{}",
+                if before { " " } else { "" },
+                text,
+                if after { " " } else { "" }
+            )
+        };
+
+        let mut stack = vec![(0, n)];
+        let mut ranges = Vec::new();
+        while let Some((i, j)) = stack.pop() {
+            if i >= j {
+                continue;
+            }
+            ranges.push((i, j));
+            let k = choice[i][j];
+            if k == 0 {
+                stack.push((i + 1, j));
+            } else {
+                stack.push((k + 1, j));
+                stack.push((i + 1, k));
+            }
+        }
+        // Inner ranges are emitted first, so that insertions at the same column
+        // nest properly once sorted by column.
+        for (i, j) in ranges.into_iter().rev() {
+            let a = &found[i];
+            let k = choice[i][j];
+            if k == 0 {
+                edits.push(if a.open {
+                    Edit::Insert {
+                        column: end(j),
+                        text: pad(end(j), 0, text(a, false)),
+                    }
+                } else {
+                    Edit::Delete {
+                        column: a.column,
+                        text: original(a),
+                    }
+                });
+                continue;
+            }
+            let b = &found[k];
+            let substitute = |f: &Found, to: String| Edit::Substitute {
+                column: f.column,
+                from: original(f),
+                to: pad(f.column, f.len, to),
+            };
+            match (a.open, b.open) {
+                (true, false) if a.pair == b.pair => (),
+                (true, _) => edits.push(substitute(b, text(a, false))),
+                (false, false) => edits.push(substitute(a, text(b, true))),
+                (false, true) => {
+                    edits.push(substitute(a, text(a, true)));
+                    edits.push(substitute(b, text(a, false)));
+                }
+            }
+        }
+        edits.sort_by_key(|edit| (edit.column(), !matches!(edit, Edit::Insert { .. })));
+        done(edits)
+    }
+
+    /// Applies sorted edits, returning the edited line and the marked-up one.
+    fn apply(line: &str, edits: &[Edit]) -> (String, String) {
+        let chars: Vec<char> = line.chars().collect();
+        let (mut out, mut marked) = (String::new(), String::new());
+        let mut it = edits.iter().peekable();
+        let mut column = 1;
+        while column <= chars.len() + 1 {
+            let mut skip = 0;
+            while let Some(edit) = it.next_if(|edit| edit.column() == column) {
+                match edit {
+                    Edit::Insert { text, .. } => {
+                        out.push_str(text);
+                        marked.push_str(&format!("{{+{}+}}", text));
+                    }
+                    Edit::Delete { text, .. } => {
+                        skip = text.chars().count();
+                        marked.push_str(&format!("[-{}-]", text));
+                    }
+                    Edit::Substitute { from, to, .. } => {
+                        skip = from.chars().count();
+                        out.push_str(to);
+                        marked.push_str(&format!("[-{}-]{{+{}+}}", from, to));
+                    }
+                }
+            }
+            if skip > 0 {
+                column += skip;
+                continue;
+            }
+            if let Some(c) = chars.get(column - 1) {
+                out.push(*c);
+                marked.push(*c);
+            }
+            column += 1;
+        }
+        (out, marked)
+    }
+}
+
+/// Brackets plus the delimiters given as `--pair OPEN CLOSE` or
+/// `--quote OPEN CLOSE` arguments.
+fn delimiters(args: &[String]) -> Option<Delimiters> {
+    let mut delimiters = Delimiters::brackets();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (open, close) = (args.next()?, args.next()?);
+        delimiters = match arg.as_str() {
+            "--pair" => delimiters.pair(open, close),
+            "--quote" => delimiters.quote(open, close),
+            _ => return None,
+        };
+    }
+    Some(delimiters)
+}
+
+/// Problem found by `check`, if any, as a message.
+fn lint(delimiters: &Delimiters, check: &Check) -> Option<String> {
+    match check {
+        Check::Ok => None,
+        Check::Corrupted {
+            line,
+            column,
+            expected: Some(expected),
+            found,
+        } => Some(format!(
+            "{}:{}: expected {}, found {}",
+            line, column, expected, found
+        )),
+        Check::Corrupted {
+            line,
+            column,
+            expected: None,
+            found,
+        } => Some(format!("{}:{}: unexpected {}", line, column, found)),
+        Check::Incomplete(remaining) => {
+            let closers: Vec<&str> = remaining
+                .iter()
+                .map(|i| delimiters.pairs[*i].close.as_str())
+                .collect();
+            Some(format!("end of input: expected {}", closers.join(" ")))
+        }
+    }
+}
+
+const USAGE: &str = "usage: day10 [repair | lint [--pair OPEN CLOSE | --quote OPEN CLOSE]...]";
+
+/// Usage: day10 [repair | lint [--pair OPEN CLOSE | --quote OPEN CLOSE]...] < input
+///
+/// With `repair`, prints the number of edits, the marked-up line and the
+/// repaired line, tab-separated, for each corrupted line instead. With
+/// `lint`, checks the whole input as one text, e.g. a source file, with the
+/// extra delimiters given, and reports the first problem.
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(|arg| arg.as_str()) {
+        None => (),
+        Some("repair") if args.len() == 1 => {
+            let delimiters = Delimiters::brackets();
+            for line in lines() {
+                if let Check::Corrupted { .. } = check(&delimiters, &line) {
+                    let repair = repair(&delimiters, &line);
+                    println!("{}\t{}\t{}", repair.edits.len(), repair.marked, repair.line);
+                }
+            }
+            return;
+        }
+        Some("lint") => {
+            let Some(delimiters) = delimiters(&args[1..]) else {
+                eprintln!("{}", USAGE);
+                std::process::exit(2);
+            };
+            let text = match io::read_to_string(io::stdin()) {
+                Ok(text) => text,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            };
+            if let Some(message) = lint(&delimiters, &check(&delimiters, &text)) {
+                println!("{}", message);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(_) => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+
+    let delimiters = Delimiters::brackets();
+    let mut part1 = 0;
+    let mut scores = Vec::new();
+    let mut overflow = None;
+    let result = stream(
+        &delimiters,
+        io::stdin().lock(),
+        1 << 16,
+        |number, check| match check {
+            Check::Corrupted { found, .. } => part1 += cost1(&found),
+            Check::Incomplete(remaining) => {
+                let closers = remaining
+                    .iter()
+                    .map(|i| delimiters.pairs[*i].close.as_str());
+                match score(closers) {
+                    Some(score) => scores.push(score),
+                    None => overflow = overflow.or(Some(number)),
+                }
+            }
+            Check::Ok => (),
+        },
+    );
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    if let Some(number) = overflow {
+        eprintln!("line {}: completion score overflows", number);
+        std::process::exit(1);
+    }
     println!("{}", part1);
 
-    let mut scores = input
-        .iter()
-        .flat_map(|line| match check(line) {
-            Check::Incomplete(remaining) => Some(score(&remaining)),
-            _ => None,
+    match median(&mut scores) {
+        Some(mid) => println!("{}", mid),
+        None => println!("no incomplete lines"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::repair::Edit;
+    use super::*;
+
+    fn corrupted(line: usize, column: usize, expected: Option<&str>, found: &str) -> Check {
+        Check::Corrupted {
+            line,
+            column,
+            expected: expected.map(|s| s.to_string()),
+            found: found.to_string(),
+        }
+    }
+
+    fn incomplete(delimiters: &Delimiters, closers: &[&str]) -> Check {
+        let pair = |close: &&str| {
+            let pair = delimiters
+                .pairs
+                .iter()
+                .position(|pair| pair.close == *close);
+            pair.unwrap()
+        };
+        Check::Incomplete(closers.iter().map(pair).collect())
+    }
+
+    #[test]
+    fn test_brackets() {
+        let delimiters = Delimiters::brackets();
+        let cases = vec![
+            ("{([(<{}[<>[]}>{[]{[(<()>", corrupted(1, 13, Some("]"), "}")),
+            ("[[<[([]))<([[{}[[()]]]", corrupted(1, 9, Some("]"), ")")),
+            ("())", corrupted(1, 3, None, ")")),
+            (
+                "[({(<(())[]>[[{[]{<()<>>",
+                incomplete(&delimiters, &["}", "}", "]", "]", ")", "}", ")", "]"]),
+            ),
+            ("(a[b]c)", Check::Ok),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(check(&delimiters, text), expected, "{}", text);
+        }
+
+        let remaining = match check(&delimiters, "<{([{{}}[<[[[<>{}]]]>[]]") {
+            Check::Incomplete(remaining) => remaining,
+            _ => unreachable!(),
+        };
+        let closers = remaining
+            .iter()
+            .map(|i| delimiters.pairs[*i].close.as_str());
+        assert_eq!(score(closers), Some(294));
+    }
+
+    #[test]
+    fn test_score_overflow() {
+        assert_eq!(score([">"; 27]), Some(5usize.pow(27) - 1));
+        assert_eq!(score([">"; 28]), None);
+        assert_eq!(score([")"; 30]), None);
+    }
+
+    #[test]
+    fn test_words_and_quotes() {
+        let delimiters = Delimiters::brackets()
+            .pair("begin", "end")
+            .quote("\"", "\"")
+            .quote("/*", "*/");
+
+        let text = "begin\n  f(\"(]\", 'x') /* ) */\n  blend(x)\nend";
+        assert_eq!(check(&delimiters, text), Check::Ok);
+
+        let text = "begin\n  f(\"\\\"\", x]\nend";
+        assert_eq!(check(&delimiters, text), corrupted(2, 12, Some(")"), "]"));
+
+        let text = "begin\n  if (x) begin\n    y\n  end\n)";
+        assert_eq!(check(&delimiters, text), corrupted(5, 1, Some("end"), ")"));
+
+        let text = "begin (\"abc";
+        let closers = ["\"", ")", "end"];
+        assert_eq!(check(&delimiters, text), incomplete(&delimiters, &closers));
+    }
+
+    #[test]
+    fn test_lint() {
+        let args = |s: &str| {
+            s.split_whitespace()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+        };
+        let table = delimiters(&args("--pair begin end --quote \" \"")).unwrap();
+        assert_eq!(table.pairs.len(), 6);
+        assert!(table.pairs[5].quote);
+        for bad in ["--pair begin", "--pair", "--brace { }"] {
+            assert!(delimiters(&args(bad)).is_none(), "{}", bad);
+        }
+
+        let cases = [
+            ("begin\n  f(\"]\")\nend", None),
+            ("begin\n  f(x]\nend", Some("2:6: expected ), found ]")),
+            ("f(x))", Some("1:5: unexpected )")),
+            ("begin [\"", Some("end of input: expected \" ] end")),
+        ];
+        for (text, expected) in cases {
+            let actual = lint(&table, &check(&table, text));
+            assert_eq!(actual.as_deref(), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_repair() {
+        let delimiters = Delimiters::brackets();
+        let cases = vec![
+            ("(a[b]c)", 0, "(a[b]c)", "(a[b]c)"),
+            ("((", 1, "()", "([-(-]{+)+}"),
+            ("(]", 1, "()", "([-]-]{+)+}"),
+            ("][", 2, "[]", "[-]-][{+]+}"),
+            ("a)b", 1, "ab", "a[-)-]b"),
+            ("[(", 1, "[]", "[[-(-]{+]+}"),
+            ("(", 1, "()", "({+)+}"),
+            ("<[(", 2, "<[]>", "<[[-(-]{+]+}{+>+}"),
+            ("{([(<{}[<>[]}>{[]{[(<()>", 5, "", ""),
+        ];
+        for (line, cost, repaired, marked) in cases {
+            let actual = repair(&delimiters, line);
+            assert_eq!(actual.edits.len(), cost, "{} {:?}", line, actual);
+            assert_eq!(check(&delimiters, &actual.line), Check::Ok, "{:?}", actual);
+            if !repaired.is_empty() {
+                assert_eq!(actual.line, repaired);
+                assert_eq!(actual.marked, marked);
+            }
+        }
+    }
+
+    #[test]
+    fn test_repair_words() {
+        let delimiters = Delimiters::brackets().pair("begin", "end");
+        let actual = repair(&delimiters, "begin (x] end");
+        assert_eq!(
+            actual.edits,
+            vec![Edit::Substitute {
+                column: 9,
+                from: "]".to_string(),
+                to: ")".to_string(),
+            }]
+        );
+        assert_eq!(actual.line, "begin (x) end");
+
+        let actual = repair(&delimiters, "begin x");
+        assert_eq!(actual.line, "begin x end");
+        assert_eq!(actual.marked, "begin x{+ end+}");
+        assert_eq!(check(&delimiters, &actual.line), Check::Ok);
+
+        // Substituted words are padded like inserted ones.
+        let actual = repair(&delimiters, "begin x]");
+        assert_eq!(actual.edits.len(), 1);
+        assert_eq!(actual.line, "begin x end");
+        assert_eq!(actual.marked, "begin x[-]-]{+ end+}");
+    }
+
+    #[test]
+    fn test_repair_quotes() {
+        let delimiters = Delimiters::brackets()
+            .pair("begin", "end")
+            .quote("\"", "\"")
+            .quote("/*", "*/");
+        let cases = vec![
+            ("(\"(\"]", 1, "(\"(\")"),
+            ("(\")", 2, "(\")\")"),
+            ("(x */)", 1, "(x )"),
+            ("begin /* end */ x", 1, "begin /* end */ x end"),
+            ("[\"\\\"]\"}", 1, "[\"\\\"]\"]"),
+        ];
+        for (line, cost, repaired) in cases {
+            let actual = repair(&delimiters, line);
+            assert_eq!(actual.edits.len(), cost, "{} {:?}", line, actual);
+            assert_eq!(actual.line, repaired);
+        }
+
+        let lines = [
+            "begin x]",
+            "(begin x)",
+            "end (\"x",
+            "/* ( */ ]) begin",
+            "{\"}\" /* { */ begin ] end",
+            "x\") \"(",
+        ];
+        for line in lines {
+            let actual = repair(&delimiters, line);
+            assert_eq!(check(&delimiters, &actual.line), Check::Ok, "{:?}", actual);
+        }
+    }
+
+    #[test]
+    fn test_stream() {
+        let delimiters = Delimiters::brackets()
+            .pair("begin", "end")
+            .quote("\"", "\"");
+        let lines = [
+            "[({(<(())[]>[[{[]{<()<>>",
+            "{([(<{}[<>[]}>{[]{[(<()>",
+            "begin \"é)\\\"\" (blend) end",
+            "begin (x) endx",
+            "",
+            "()",
+        ];
+        let input = lines.join("\n");
+        let expected: Vec<(usize, Check)> = lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| match check(&delimiters, line) {
+                Check::Corrupted {
+                    column,
+                    expected,
+                    found,
+                    ..
+                } => (i + 1, corrupted(i + 1, column, expected.as_deref(), &found)),
+                check => (i + 1, check),
+            })
+            .collect();
+        assert_eq!(expected[1].1, corrupted(2, 13, Some("]"), "}"));
+        assert_eq!(expected[2].1, Check::Ok);
+
+        for chunk in [1, 2, 3, 5, 7, 64] {
+            let mut actual = Vec::new();
+            stream(&delimiters, input.as_bytes(), chunk, |i, check| {
+                actual.push((i, check))
+            })
+            .unwrap();
+            assert_eq!(actual, expected, "chunk={}", chunk);
+        }
+    }
+
+    #[test]
+    fn test_stream_long_line() {
+        let delimiters = Delimiters::brackets();
+        let depth = 100_000;
+        let line = "(".repeat(depth) + &")".repeat(depth - 1) + "]";
+        let mut actual = Vec::new();
+        stream(&delimiters, line.as_bytes(), 4096, |i, check| {
+            actual.push((i, check))
         })
-        .collect::<Vec<_>>();
-    scores.sort_unstable();
+        .unwrap();
+        let expected = Check::Corrupted {
+            line: 1,
+            column: 2 * depth,
+            expected: Some(")".to_string()),
+            found: "]".to_string(),
+        };
+        assert_eq!(actual, vec![(1, expected)]);
+    }
 
-    let mid = scores[scores.len() / 2];
-    println!("{}", mid);
+    #[test]
+    fn test_median() {
+        assert_eq!(median(&mut []), None);
+        assert_eq!(median(&mut [5]), Some(5));
+        assert_eq!(median(&mut [9, 1, 5, 3, 7]), Some(5));
+    }
+
+    #[test]
+    fn test_stream_invalid() {
+        let delimiters = Delimiters::brackets();
+        for input in [&b"(]\n\xff\n(>\n[}\n"[..], &b"(]\n()\xc3"[..]] {
+            let mut actual = Vec::new();
+            let error =
+                stream(&delimiters, input, 2, |i, check| actual.push((i, check))).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(error.to_string(), "line 2: invalid UTF-8");
+            assert_eq!(actual, vec![(1, corrupted(1, 2, Some(")"), "]"))]);
+        }
+    }
 }
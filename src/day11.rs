@@ -1,102 +1,393 @@
 use advent_of_code_2021::util::lines;
-use std::collections::HashSet;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 
 type Grid = Vec<Vec<u8>>;
 
-fn adj(grid: &[Vec<u8>], row: usize, col: usize) -> Vec<(usize, usize)> {
-    let (rows, cols) = (grid.len(), grid[0].len());
-    let mut adj = Vec::with_capacity(4);
-    if row > 0 {
-        adj.push((row - 1, col));
-    }
-    if row < rows - 1 {
-        adj.push((row + 1, col));
+const MOORE: [(isize, isize); 8] = [
+    (-1, 0),
+    (1, 0),
+    (0, -1),
+    (0, 1),
+    (-1, -1),
+    (-1, 1),
+    (1, -1),
+    (1, 1),
+];
+
+const ORTHOGONAL: [(isize, isize); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+
+#[derive(Debug, Clone)]
+struct Rules {
+    /// An octopus flashes once its energy goes above this level.
+    threshold: u8,
+    /// Offsets of the octopuses that receive energy from a flash.
+    neighbours: Vec<(isize, isize)>,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            threshold: 9,
+            neighbours: MOORE.to_vec(),
+        }
     }
-    if col > 0 {
-        adj.push((row, col - 1));
+}
+
+impl Rules {
+    fn orthogonal() -> Self {
+        Self {
+            neighbours: ORTHOGONAL.to_vec(),
+            ..Self::default()
+        }
     }
-    if col < cols - 1 {
-        adj.push((row, col + 1));
+}
+
+fn adj<'a>(
+    grid: &[Vec<u8>],
+    rules: &'a Rules,
+    row: usize,
+    col: usize,
+) -> impl Iterator<Item = (usize, usize)> + 'a {
+    let (rows, cols) = (grid.len(), grid[0].len());
+    rules.neighbours.iter().filter_map(move |&(dr, dc)| {
+        let row = row.checked_add_signed(dr).filter(|row| *row < rows)?;
+        let col = col.checked_add_signed(dc).filter(|col| *col < cols)?;
+        Some((row, col))
+    })
+}
+
+/// Advances the grid by one step and returns the number of flashes.
+///
+/// Every cell enters the work queue exactly once, when its energy first goes
+/// above the threshold, so a step costs O(cells + flashes).
+fn step(grid: &mut Grid, rules: &Rules) -> usize {
+    let threshold = rules.threshold;
+    assert!(threshold < u8::MAX);
+
+    let mut queue = Vec::new();
+    for (row, line) in grid.iter_mut().enumerate() {
+        for (col, cell) in line.iter_mut().enumerate() {
+            *cell = cell.saturating_add(1);
+            if *cell > threshold {
+                queue.push((row, col));
+            }
+        }
     }
 
-    if row > 0 && col > 0 {
-        adj.push((row - 1, col - 1));
+    let mut flashes = 0;
+    while let Some((row, col)) = queue.pop() {
+        flashes += 1;
+        for (row, col) in adj(grid, rules, row, col) {
+            let cell = &mut grid[row][col];
+            *cell = cell.saturating_add(1);
+            if *cell == threshold + 1 {
+                queue.push((row, col));
+            }
+        }
     }
-    if row > 0 && col < cols - 1 {
-        adj.push((row - 1, col + 1));
+
+    if flashes > 0 {
+        grid.iter_mut()
+            .flat_map(|row| row.iter_mut())
+            .filter(|cell| **cell > threshold)
+            .for_each(|cell| *cell = 0);
     }
-    if row < rows - 1 && col > 0 {
-        adj.push((row + 1, col - 1));
+    flashes
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct Cycle {
+    /// First step whose resulting state repeats; 0 is the initial state.
+    start: usize,
+    period: usize,
+}
+
+#[derive(Debug)]
+struct Analysis {
+    /// Number of flashes at steps 1, 2, ... until the cycle closes.
+    flashes: Vec<usize>,
+    /// `None` if no state repeated within the step limit.
+    cycle: Option<Cycle>,
+    /// First step at which every octopus flashed. `None` with a known cycle
+    /// means the grid never synchronizes.
+    synchronized: Option<usize>,
+}
+
+impl Analysis {
+    /// Flashes at the given step (1-based), extrapolated through the cycle.
+    fn flashes_at(&self, step: usize) -> Option<usize> {
+        assert!(step > 0);
+        if let Some(n) = self.flashes.get(step - 1) {
+            return Some(*n);
+        }
+        let cycle = self.cycle?;
+        let offset = (step - 1 - cycle.start) % cycle.period;
+        Some(self.flashes[cycle.start + offset])
     }
-    if row < rows - 1 && col < cols - 1 {
-        adj.push((row + 1, col + 1));
+
+    /// Total flashes over the first `steps` steps.
+    fn total(&self, steps: usize) -> Option<usize> {
+        (1..=steps).map(|step| self.flashes_at(step)).sum()
     }
-    adj
 }
 
-fn done(grid: &[Vec<u8>]) -> bool {
-    grid.iter().all(|row| row.iter().all(|cell| *cell <= 9))
+fn fingerprint(grid: &[Vec<u8>]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    grid.hash(&mut hasher);
+    hasher.finish()
 }
 
-fn cells(grid: &[Vec<u8>]) -> Vec<(usize, usize, u8)> {
-    let (rows, cols) = (grid.len(), grid[0].len());
-    (0..rows)
-        .into_iter()
-        .flat_map(move |row| {
-            (0..cols)
-                .into_iter()
-                .map(move |col| (row, col, grid[row][col]))
-        })
+/// Steps the grid until a state repeats or `limit` steps have been taken.
+///
+/// Only a hash of each state is kept; a matching hash is confirmed by
+/// replaying the steps up to the earlier state, so memory stays O(steps)
+/// even for very large grids.
+fn analyse(grid: &[Vec<u8>], rules: &Rules, limit: usize) -> Analysis {
+    let cells: usize = grid.iter().map(|row| row.len()).sum();
+    let replay = |steps: usize| {
+        let mut grid = grid.to_vec();
+        (0..steps).for_each(|_| {
+            step(&mut grid, rules);
+        });
+        grid
+    };
+
+    let mut seen: HashMap<u64, usize> = HashMap::new();
+    let mut current = grid.to_vec();
+    seen.insert(fingerprint(&current), 0);
+
+    let mut analysis = Analysis {
+        flashes: Vec::new(),
+        cycle: None,
+        synchronized: None,
+    };
+    for n in 1..=limit {
+        let flashes = step(&mut current, rules);
+        analysis.flashes.push(flashes);
+        if flashes == cells && analysis.synchronized.is_none() {
+            analysis.synchronized = Some(n);
+        }
+
+        let hash = fingerprint(&current);
+        if let Some(&start) = seen.get(&hash) {
+            if replay(start) == current {
+                analysis.cycle = Some(Cycle {
+                    start,
+                    period: n - start,
+                });
+                break;
+            }
+        }
+        seen.insert(hash, n);
+    }
+    analysis
+}
+
+fn parse(lines: &[String]) -> Grid {
+    lines
+        .iter()
+        .map(|line| line.chars().map(|c| c as u8 - b'0').collect())
         .collect()
 }
 
-fn all(grid: &[Vec<u8>]) -> bool {
-    cells(grid).iter().all(|(_, _, cell)| *cell == 0)
+/// Usage: day11 [orthogonal] < input
+///
+/// With `orthogonal`, a flash only reaches the four orthogonal neighbours.
+fn main() {
+    let rules = match std::env::args().nth(1).as_deref() {
+        None => Rules::default(),
+        Some("orthogonal") => Rules::orthogonal(),
+        Some(_) => {
+            eprintln!("usage: day11 [orthogonal]");
+            std::process::exit(2);
+        }
+    };
+    let grid = parse(&lines());
+    let analysis = analyse(&grid, &rules, 100_000);
+
+    println!(
+        "{}",
+        analysis.total(100).expect("no cycle within the limit")
+    );
+    match analysis.synchronized {
+        Some(n) => println!("{}", n),
+        None if analysis.cycle.is_some() => println!("never synchronizes"),
+        None => println!("no cycle within the limit"),
+    }
 }
 
-fn step(grid: &mut Grid) -> usize {
-    let mut fired: HashSet<(usize, usize)> = HashSet::new();
-
-    grid.iter_mut()
-        .for_each(|row| row.iter_mut().for_each(|cell| *cell += 1));
-
-    while !done(grid) {
-        cells(grid)
-            .into_iter()
-            .filter_map(|(row, col, cell)| if cell > 9 { Some((row, col)) } else { None })
-            .for_each(|cell @ (row, col)| {
-                fired.insert(cell);
-                grid[row][col] = 0;
-                adj(grid, row, col).into_iter().for_each(|(row, col)| {
-                    grid[row][col] += 1;
-                })
-            });
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "5483143223 2745854711 5264556173 6141336146 6357385478 \
+                           4167524645 2176841721 6882881134 4846848554 5283751526";
+
+    fn input(s: &str) -> Grid {
+        let lines: Vec<String> = s.split_whitespace().map(|s| s.to_string()).collect();
+        parse(&lines)
     }
 
-    fired.iter().cloned().for_each(|(row, col)| {
-        grid[row][col] = 0;
-    });
-    fired.len()
-}
+    /// The original full-grid rescan, kept as a reference.
+    fn rescan(grid: &mut Grid) -> usize {
+        grid.iter_mut()
+            .for_each(|row| row.iter_mut().for_each(|cell| *cell += 1));
+        let mut fired = vec![vec![false; grid[0].len()]; grid.len()];
+        let mut flashes = 0;
+        loop {
+            let mut changed = false;
+            for row in 0..grid.len() {
+                for col in 0..grid[0].len() {
+                    if grid[row][col] > 9 && !fired[row][col] {
+                        fired[row][col] = true;
+                        flashes += 1;
+                        changed = true;
+                        let rules = Rules::default();
+                        for (row, col) in adj(grid, &rules, row, col).collect::<Vec<_>>() {
+                            grid[row][col] += 1;
+                        }
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        for (row, line) in fired.iter().enumerate() {
+            for (col, fired) in line.iter().enumerate() {
+                if *fired {
+                    grid[row][col] = 0;
+                }
+            }
+        }
+        flashes
+    }
 
-fn main() {
-    let mut grid: Grid = lines()
-        .into_iter()
-        .map(|line| line.chars().into_iter().map(|c| c as u8 - b'0').collect())
-        .collect();
+    #[test]
+    fn test_example() {
+        let rules = Rules::default();
+        let mut grid = input(EXAMPLE);
+        let flashes: usize = (0..10).map(|_| step(&mut grid, &rules)).sum();
+        assert_eq!(flashes, 204);
+
+        let analysis = analyse(&input(EXAMPLE), &rules, 1000);
+        assert_eq!(analysis.total(10), Some(204));
+        assert_eq!(analysis.total(100), Some(1656));
+        assert_eq!(analysis.synchronized, Some(195));
+        // Once synchronized the whole grid flashes every ten steps.
+        assert_eq!(
+            analysis.cycle,
+            Some(Cycle {
+                start: 195,
+                period: 10
+            })
+        );
+        assert_eq!(analysis.flashes_at(10_195), Some(100));
+        assert_eq!(analysis.flashes_at(10_196), Some(0));
+    }
+
+    #[test]
+    fn test_rescan() {
+        let grids = [
+            EXAMPLE,
+            "11111 19991 19191 19991 11111",
+            "9",
+            "98 89",
+            "123456789 987654321",
+            "9 8 7 9 0 9",
+            "0000 0990 0990 0000",
+            "5959 9595 5959",
+        ];
+        for grid in grids {
+            let mut grid = input(grid);
+            let mut expected = grid.clone();
+            for _ in 0..50 {
+                assert_eq!(step(&mut grid, &Rules::default()), rescan(&mut expected));
+                assert_eq!(grid, expected);
+            }
+        }
+    }
 
-    let n = 100;
-    let mut part1 = 0usize;
-    for _ in 0..n {
-        let n = step(&mut grid);
-        part1 += n;
+    #[test]
+    fn test_large() {
+        // A single 9 in a 1000x1000 grid flashes alone, and eight steps later
+        // its neighbours set off a cascade over the whole grid.
+        let side = 1000;
+        let rules = Rules::default();
+        let mut grid = vec![vec![0u8; side]; side];
+        grid[side / 2][side / 2] = 9;
+        assert_eq!(step(&mut grid, &rules), 1);
+        assert_eq!(grid[side / 2][side / 2], 0);
+        assert_eq!(grid[side / 2 + 1][side / 2], 2);
+        assert_eq!(grid[0][0], 1);
+        let flashes: usize = (0..7).map(|_| step(&mut grid, &rules)).sum();
+        assert_eq!(flashes, 0);
+        assert_eq!(step(&mut grid, &rules), side * side);
+        assert!(grid.iter().flatten().all(|cell| *cell == 0));
     }
-    println!("{}", part1);
 
-    let mut part2 = n;
-    while !all(&grid) {
-        step(&mut grid);
-        part2 += 1;
+    #[test]
+    fn test_never_synchronizes() {
+        // The two diagonals take turns flashing, each flash lifting the other
+        // diagonal just short of the threshold.
+        for rules in [Rules::default(), Rules::orthogonal()] {
+            let analysis = analyse(&input("90 09"), &rules, 1000);
+            assert_eq!(analysis.synchronized, None);
+            assert_eq!(
+                analysis.cycle,
+                Some(Cycle {
+                    start: 0,
+                    period: 8
+                })
+            );
+            assert_eq!(analysis.total(80), Some(40));
+        }
+
+        // Whether a grid synchronizes depends on the neighbourhood.
+        let grid = input("12 34");
+        let moore = analyse(&grid, &Rules::default(), 1000);
+        assert_eq!(moore.synchronized, Some(6));
+        let orthogonal = analyse(&grid, &Rules::orthogonal(), 1000);
+        assert_eq!(orthogonal.synchronized, None);
+        assert_eq!(
+            orthogonal.cycle,
+            Some(Cycle {
+                start: 7,
+                period: 9
+            })
+        );
+
+        let mut grid = grid.clone();
+        let rules = Rules::orthogonal();
+        for n in 1..=50 {
+            assert_eq!(orthogonal.flashes_at(n), Some(step(&mut grid, &rules)));
+        }
+    }
+
+    #[test]
+    fn test_threshold() {
+        let rules = Rules {
+            threshold: 3,
+            ..Rules::default()
+        };
+        let mut grid = input("000 030 000");
+        assert_eq!(step(&mut grid, &rules), 1);
+        assert_eq!(grid, input("222 202 222"));
+        assert_eq!(step(&mut grid, &rules), 0);
+        assert_eq!(step(&mut grid, &rules), 9);
+        assert_eq!(grid, input("000 000 000"));
+
+        let analysis = analyse(&input("000 030 000"), &rules, 100);
+        assert_eq!(analysis.synchronized, Some(3));
+        assert_eq!(
+            analysis.cycle,
+            Some(Cycle {
+                start: 3,
+                period: 4
+            })
+        );
+        assert_eq!(analysis.total(10), Some(1 + 9 + 9));
     }
-    println!("{}", part2);
 }
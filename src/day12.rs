@@ -1,5 +1,7 @@
 use advent_of_code_2021::util::input;
-use std::collections::HashMap;
+use paths::{is_valid1, is_valid2, Paths};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::str::FromStr;
 
 #[derive(Debug, Clone)]
@@ -27,62 +29,691 @@ fn graph(edges: &[Edge]) -> Graph {
     graph
 }
 
-fn is_small(node: &str) -> bool {
-    node.chars().all(|c| c.is_lowercase())
-}
-
 fn is_large(node: &str) -> bool {
     node.chars().all(|c| c.is_uppercase())
 }
 
-fn is_valid1(node: &str, path: &[String]) -> bool {
-    is_large(node) || (node != "start" && path.iter().all(|n| n != node))
+#[derive(Debug, Eq, PartialEq)]
+enum Error {
+    /// Pairs of large caves joined by an edge: a path could go back and
+    /// forth between them forever.
+    AdjacentLarge(Vec<(String, String)>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::AdjacentLarge(pairs) => {
+                let pairs: Vec<String> =
+                    pairs.iter().map(|(a, b)| format!("{}-{}", a, b)).collect();
+                write!(
+                    f,
+                    "adjacent large caves {}, the number of paths is infinite",
+                    pairs.join(", ")
+                )
+            }
+        }
+    }
+}
+
+/// Caves reachable from `from` without passing through `start` or `end`,
+/// which paths never re-enter and never leave.
+fn reachable<'a>(graph: &'a Graph, from: &'a str, start: &str) -> HashSet<&'a str> {
+    let mut seen: HashSet<&str> = HashSet::from([from]);
+    let mut queue = vec![from];
+    while let Some(cave) = queue.pop() {
+        if cave != from && (cave == start || cave == "end") {
+            continue;
+        }
+        for next in graph.get(cave).into_iter().flatten() {
+            if seen.insert(next) {
+                queue.push(next);
+            }
+        }
+    }
+    seen
+}
+
+/// Checks that the number of paths from `start` is finite, i.e. no two large
+/// caves that a path can go through are adjacent. The start cave is never
+/// revisited, so it does not count. Edges are reported once each, sorted.
+fn check(graph: &Graph, start: &str) -> Result<(), Error> {
+    let from_start = reachable(graph, start, start);
+    let to_end = reachable(graph, "end", start);
+    let inside = |cave: &str| {
+        is_large(cave)
+            && cave != start
+            && cave != "end"
+            && from_start.contains(cave)
+            && to_end.contains(cave)
+    };
+
+    let mut pairs: Vec<(String, String)> = graph
+        .iter()
+        .filter(|(cave, _)| inside(cave))
+        .flat_map(|(cave, next)| {
+            next.iter()
+                .filter(move |next| inside(next) && cave <= *next)
+                .map(move |next| (cave.clone(), next.clone()))
+        })
+        .collect();
+    pairs.sort();
+    pairs.dedup();
+    if pairs.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::AdjacentLarge(pairs))
+    }
+}
+
+/// Graphviz rendering of the cave system: start and end are double circles,
+/// small caves ellipses and large caves grey boxes. Edges between large
+/// caves are drawn in red.
+fn dot(graph: &Graph) -> String {
+    let mut caves: Vec<&String> = graph.keys().collect();
+    caves.sort();
+
+    let mut dot = String::from("graph caves {\n");
+    for cave in caves.iter() {
+        let style = if *cave == "start" || *cave == "end" {
+            "shape=doublecircle"
+        } else if is_large(cave) {
+            "shape=box, style=filled, fillcolor=lightgrey"
+        } else {
+            "shape=ellipse"
+        };
+        dot += &format!("    \"{}\" [{}];\n", cave, style);
+    }
+
+    let mut edges: Vec<(&String, &String)> = caves
+        .iter()
+        .flat_map(|cave| {
+            let mut loops = 0;
+            graph[*cave].iter().filter_map(move |next| {
+                // Both ends of a loop are listed under the same cave.
+                if next == *cave {
+                    loops += 1;
+                }
+                (*cave < next || (next == *cave && loops % 2 == 1)).then_some((*cave, next))
+            })
+        })
+        .collect();
+    edges.sort();
+    for (a, b) in edges {
+        let style = if is_large(a) && is_large(b) {
+            " [color=red]"
+        } else {
+            ""
+        };
+        dot += &format!("    \"{}\" -- \"{}\"{};\n", a, b, style);
+    }
+    dot += "}\n";
+    dot
+}
+
+/// Enumeration of the actual paths, used to inspect and check path sets.
+mod paths {
+    use super::{is_large, Graph};
+    use std::collections::HashMap;
+
+    fn is_small(node: &str) -> bool {
+        node.chars().all(|c| c.is_lowercase())
+    }
+
+    pub(super) fn is_valid1(node: &str, path: &[&str]) -> bool {
+        is_large(node) || (node != "start" && path.iter().all(|n| *n != node))
+    }
+
+    pub(super) fn is_valid2(node: &str, path: &[&str]) -> bool {
+        is_large(node)
+            || node != "start" && {
+                let count = counts(path);
+                // 1. path does not contain this small node
+                !count.contains_key(node)
+            // 2. path contains this small node only once AND any other small node only once
+            || count.into_iter()
+                .filter(|(n, _)| is_small(n))
+                .all(|(_, k)| k == 1)
+            }
+    }
+
+    pub(super) fn counts<'a>(path: &[&'a str]) -> HashMap<&'a str, usize> {
+        let mut counts = HashMap::new();
+        for node in path.iter().cloned() {
+            *counts.entry(node).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Lazy depth-first enumeration of all start-end paths, each yielded as the
+    /// sequence of caves it visits. With adjacent large caves (see `check`) the
+    /// iteration never ends.
+    pub(super) struct Paths<'a, F> {
+        graph: &'a Graph,
+        is_valid: F,
+        /// Caves that every yielded path must pass through.
+        through: Vec<String>,
+        /// Maximum number of caves in a path, including start and end.
+        max_len: Option<usize>,
+        path: Vec<&'a str>,
+        /// Index of the next neighbour to try for each cave on the path.
+        next: Vec<usize>,
+    }
+
+    impl<'a, F> Paths<'a, F>
+    where
+        F: Fn(&str, &[&str]) -> bool,
+    {
+        pub(super) fn new(graph: &'a Graph, is_valid: F) -> Self {
+            let (path, next) = match graph.get_key_value("start") {
+                Some((start, _)) => (vec![start.as_str()], vec![0]),
+                None => (Vec::new(), Vec::new()),
+            };
+            Self {
+                graph,
+                is_valid,
+                through: Vec::new(),
+                max_len: None,
+                path,
+                next,
+            }
+        }
+
+        pub(super) fn through(mut self, cave: &str) -> Self {
+            self.through.push(cave.to_string());
+            self
+        }
+
+        pub(super) fn max_len(mut self, len: usize) -> Self {
+            self.max_len = Some(len);
+            self
+        }
+    }
+
+    impl<'a, F> Iterator for Paths<'a, F>
+    where
+        F: Fn(&str, &[&str]) -> bool,
+    {
+        type Item = Vec<&'a str>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            let graph = self.graph;
+            loop {
+                let node = *self.path.last()?;
+                let i = self.next.last_mut()?;
+                let Some(next) = graph[node].get(*i) else {
+                    self.path.pop();
+                    self.next.pop();
+                    continue;
+                };
+                *i += 1;
+
+                let len = self.path.len() + 1;
+                if self.max_len.is_some_and(|max| len > max) {
+                    continue;
+                }
+                if next == "end" {
+                    let through = self
+                        .through
+                        .iter()
+                        .all(|cave| self.path.contains(&cave.as_str()));
+                    if through {
+                        let mut path = self.path.clone();
+                        path.push(next);
+                        return Some(path);
+                    }
+                } else if (self.is_valid)(next, &self.path) {
+                    // A detour through `next` needs at least one more cave to end.
+                    if self.max_len.is_some_and(|max| len + 1 > max) {
+                        continue;
+                    }
+                    self.path.push(next);
+                    self.next.push(0);
+                }
+            }
+        }
+    }
+}
+
+type Count = u128;
+
+/// Bit set over the small caves.
+type Mask = Vec<u64>;
+
+/// The cave graph reduced to its small caves. A step through a large cave
+/// from `a` to `b` is folded into the weight of the edge between them, so
+/// large caves can be revisited freely without being tracked.
+struct Caves {
+    /// Weighted neighbours of each small cave.
+    edges: Vec<Vec<(usize, Count)>>,
+    start: usize,
+    end: Option<usize>,
 }
 
-fn is_valid2(node: &str, path: &[String]) -> bool {
-    is_large(node)
-        || node != "start" && {
-            let count = counts(path);
-            // 1. path does not contain this small node
-            !count.contains_key(node)
-        // 2. path contains this small node only once AND any other small node only once
-        || count.into_iter()
-            .filter(|(n, _)| is_small(n))
-            .all(|(_, k)| k == 1)
+impl Caves {
+    fn new(graph: &Graph, start: &str) -> Self {
+        let mut names: Vec<&str> = graph
+            .keys()
+            .map(|name| name.as_str())
+            .filter(|name| !is_large(name) || *name == start || *name == "end")
+            .collect();
+        names.sort_unstable();
+        let index: HashMap<&str, usize> = names.iter().enumerate().map(|(i, n)| (*n, i)).collect();
+
+        let mut weights: Vec<HashMap<usize, Count>> = vec![HashMap::new(); names.len()];
+        for (i, name) in names.iter().enumerate() {
+            for next in &graph[*name] {
+                match index.get(next.as_str()) {
+                    Some(&j) => *weights[i].entry(j).or_default() += 1,
+                    None => {
+                        for next in &graph[next] {
+                            if let Some(&j) = index.get(next.as_str()) {
+                                *weights[i].entry(j).or_default() += 1;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Self {
+            edges: weights
+                .into_iter()
+                .map(|weights| {
+                    let mut edges: Vec<(usize, Count)> = weights.into_iter().collect();
+                    edges.sort_unstable();
+                    edges
+                })
+                .collect(),
+            start: index[start],
+            end: index.get("end").cloned(),
+        }
+    }
+
+    /// Number of paths from `cave` to the end, given the small caves already
+    /// visited and the number of revisits left.
+    fn count(
+        &self,
+        cave: usize,
+        visited: &mut Mask,
+        budget: usize,
+        memo: &mut HashMap<(usize, Mask, usize), Count>,
+    ) -> Count {
+        let key = (cave, visited.clone(), budget);
+        if let Some(count) = memo.get(&key) {
+            return *count;
+        }
+
+        let mut count = 0;
+        for &(next, weight) in &self.edges[cave] {
+            if Some(next) == self.end {
+                count += weight;
+            } else if next == self.start {
+                continue;
+            } else if visited[next / 64] & (1 << (next % 64)) != 0 {
+                if budget > 0 {
+                    count += weight * self.count(next, visited, budget - 1, memo);
+                }
+            } else {
+                visited[next / 64] |= 1 << (next % 64);
+                count += weight * self.count(next, visited, budget, memo);
+                visited[next / 64] &= !(1 << (next % 64));
+            }
         }
+
+        memo.insert(key, count);
+        count
+    }
 }
 
-fn counts(path: &[String]) -> HashMap<String, usize> {
-    let mut counts = HashMap::new();
-    for node in path.iter().cloned() {
-        *counts.entry(node).or_default() += 1;
+/// Number of paths from `start` to `end` that may revisit small caves
+/// `budget` times in total. The start cave is never revisited, and large
+/// caves can be visited any number of times.
+fn count(graph: &Graph, start: &str, budget: usize) -> Result<Count, Error> {
+    if !graph.contains_key(start) {
+        return Ok(0);
     }
-    counts
+    check(graph, start)?;
+    let caves = Caves::new(graph, start);
+    let mut visited: Mask = vec![0; caves.edges.len().div_ceil(64)];
+    visited[caves.start / 64] |= 1 << (caves.start % 64);
+    Ok(caves.count(caves.start, &mut visited, budget, &mut HashMap::new()))
+}
+
+/// Options of `day12 paths`.
+#[derive(Debug, Default, Eq, PartialEq)]
+struct Options {
+    /// Allow a single small cave to be visited twice.
+    twice: bool,
+    through: Vec<String>,
+    max_len: Option<usize>,
 }
 
-fn dfs<F>(graph: &Graph, node: &str, mut path: Vec<String>, hits: &mut usize, is_valid: F)
-where
-    F: Fn(&str, &[String]) -> bool + Copy,
-{
-    path.push(node.to_string());
-    for next in &graph.get(node).cloned().unwrap_or_default() {
-        if next == "end" {
-            *hits += 1;
-        } else if is_valid(next, &path) {
-            dfs(graph, next, path.clone(), hits, is_valid);
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, ()> {
+        let mut options = Self::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--twice" => options.twice = true,
+                "--through" => options.through.push(args.next().ok_or(())?.clone()),
+                "--max-len" => {
+                    let len = args.next().ok_or(())?.parse().map_err(|_| ())?;
+                    options.max_len = Some(len);
+                }
+                _ => return Err(()),
+            }
         }
+        Ok(options)
     }
 }
 
+/// Prints the paths selected by `options`, one per line. Without a maximum
+/// length the number of paths must be finite.
+fn print_paths(graph: &Graph, options: &Options) -> Result<(), Error> {
+    if options.max_len.is_none() {
+        check(graph, "start")?;
+    }
+
+    let is_valid: fn(&str, &[&str]) -> bool = if options.twice { is_valid2 } else { is_valid1 };
+    let mut paths = Paths::new(graph, is_valid);
+    for cave in &options.through {
+        paths = paths.through(cave);
+    }
+    if let Some(len) = options.max_len {
+        paths = paths.max_len(len);
+    }
+    for path in paths {
+        println!("{}", path.join(","));
+    }
+    Ok(())
+}
+
+/// Usage: day12 [dot | paths [--twice] [--through CAVE]... [--max-len N]] < input
+///
+/// With `dot`, prints the cave system in Graphviz format instead of the
+/// path counts. With `paths`, prints every path, visiting a single small
+/// cave twice with `--twice`.
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match args.first().map(|arg| arg.as_str()) {
+        Some("paths") => match Options::parse(&args[1..]) {
+            Ok(options) => Some(options),
+            Err(()) => {
+                eprintln!("usage: day12 [dot | paths [--twice] [--through CAVE]... [--max-len N]]");
+                std::process::exit(2);
+            }
+        },
+        _ => None,
+    };
+
     let edges: Vec<Edge> = input();
     let graph = graph(&edges);
 
-    let mut part1 = 0usize;
-    dfs(&graph, "start", Vec::new(), &mut part1, is_valid1);
-    println!("{}", part1);
+    if args.first().map(|arg| arg.as_str()) == Some("dot") {
+        print!("{}", dot(&graph));
+        return;
+    }
+    if let Some(options) = options {
+        if let Err(e) = print_paths(&graph, &options) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    for budget in [0, 1] {
+        match count(&graph, "start", budget) {
+            Ok(n) => println!("{}", n),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::paths::counts;
+    use super::*;
+
+    const SMALL: &str = "start-A start-b A-c A-b b-d A-end b-end";
+    const MEDIUM: &str = "dc-end HN-start start-kj dc-start dc-HN LN-dc HN-end kj-sa kj-HN kj-dc";
+    const LARGE: &str = "fs-end he-DX fs-he start-DX pj-DX end-zg zg-sl zg-pj pj-he RW-he \
+                         fs-DX pj-RW zg-RW start-pj he-WI zg-he pj-fs start-RW";
+
+    fn parse(s: &str) -> Graph {
+        let edges: Vec<Edge> = s.split_whitespace().map(|e| e.parse().unwrap()).collect();
+        graph(&edges)
+    }
+
+    fn joined<'a>(paths: impl Iterator<Item = Vec<&'a str>>) -> Vec<String> {
+        let mut paths: Vec<String> = paths.map(|path| path.join(",")).collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn test_count() {
+        for (graph, part1, part2) in [(SMALL, 10, 36), (MEDIUM, 19, 103), (LARGE, 226, 3509)] {
+            let graph = parse(graph);
+            assert_eq!(Paths::new(&graph, is_valid1).count(), part1);
+            assert_eq!(Paths::new(&graph, is_valid2).count(), part2);
+        }
+    }
+
+    #[test]
+    fn test_paths() {
+        let graph = parse(SMALL);
+        let paths = joined(Paths::new(&graph, is_valid1));
+        assert_eq!(
+            paths,
+            vec![
+                "start,A,b,A,c,A,end",
+                "start,A,b,A,end",
+                "start,A,b,end",
+                "start,A,c,A,b,A,end",
+                "start,A,c,A,b,end",
+                "start,A,c,A,end",
+                "start,A,end",
+                "start,b,A,c,A,end",
+                "start,b,A,end",
+                "start,b,end",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_filters() {
+        let graph = parse(SMALL);
+        let through = joined(Paths::new(&graph, is_valid1).through("c"));
+        assert_eq!(
+            through,
+            vec![
+                "start,A,b,A,c,A,end",
+                "start,A,c,A,b,A,end",
+                "start,A,c,A,b,end",
+                "start,A,c,A,end",
+                "start,b,A,c,A,end",
+            ]
+        );
+
+        let short = joined(Paths::new(&graph, is_valid1).max_len(4));
+        assert_eq!(
+            short,
+            vec![
+                "start,A,b,end",
+                "start,A,end",
+                "start,b,A,end",
+                "start,b,end"
+            ]
+        );
+
+        let both = Paths::new(&graph, is_valid2).through("d").max_len(6);
+        assert_eq!(
+            joined(both),
+            vec!["start,A,b,d,b,end", "start,b,d,b,A,end", "start,b,d,b,end"]
+        );
+
+        assert_eq!(Paths::new(&graph, is_valid1).max_len(2).count(), 0);
+        assert_eq!(Paths::new(&graph, is_valid1).through("x").count(), 0);
+    }
+
+    #[test]
+    fn test_lazy() {
+        // Enough paths that collecting them all would take a while.
+        let mut edges = vec!["start-A".to_string(), "A-end".to_string()];
+        edges.extend((0..12).map(|i| format!("A-c{}", i)));
+        let graph = parse(&edges.join(" "));
+        let first = Paths::new(&graph, is_valid2).next().unwrap();
+        assert_eq!(first.first(), Some(&"start"));
+        assert_eq!(first.last(), Some(&"end"));
+        assert_eq!(Paths::new(&graph, is_valid1).take(1000).count(), 1000);
+    }
 
-    let mut part2 = 0usize;
-    dfs(&graph, "start", Vec::new(), &mut part2, is_valid2);
-    println!("{}", part2);
+    #[test]
+    fn test_dp() {
+        for (graph, part1, part2) in [(SMALL, 10, 36), (MEDIUM, 19, 103), (LARGE, 226, 3509)] {
+            let graph = parse(graph);
+            assert_eq!(count(&graph, "start", 0).unwrap(), part1);
+            assert_eq!(count(&graph, "start", 1).unwrap(), part2);
+        }
+        assert_eq!(count(&parse(SMALL), "nowhere", 1), Ok(0));
+    }
+
+    #[test]
+    fn test_budget() {
+        // Each revisit of a small cave spends one unit of the budget.
+        let within = |budget: usize| {
+            move |node: &str, path: &[&str]| {
+                if is_large(node) {
+                    return true;
+                }
+                if node == path[0] {
+                    return false;
+                }
+                let counts = counts(path);
+                let revisits: usize = counts
+                    .iter()
+                    .filter(|(n, _)| !is_large(n))
+                    .map(|(_, k)| k - 1)
+                    .sum();
+                revisits + counts.contains_key(node) as usize <= budget
+            }
+        };
+        for graph in [SMALL, MEDIUM, LARGE] {
+            let graph = parse(graph);
+            for budget in 0..3 {
+                let paths = Paths::new(&graph, within(budget)).count() as Count;
+                assert_eq!(count(&graph, "start", budget).unwrap(), paths);
+            }
+        }
+
+        // Any cave can serve as the start, which is then never revisited,
+        // while a cave named "start" becomes an ordinary small cave.
+        let swapped = parse("d-A d-b A-c A-b b-start A-end b-end");
+        let graph = parse(SMALL);
+        for budget in 0..3 {
+            assert_eq!(
+                count(&graph, "d", budget).unwrap(),
+                count(&swapped, "start", budget).unwrap()
+            );
+        }
+        assert_eq!(count(&graph, "d", 0).unwrap(), 8);
+        assert_eq!(Paths::new(&swapped, is_valid1).count(), 8);
+    }
+
+    #[test]
+    fn test_many_caves() {
+        // A chain of 200 small caves where every other link can be crossed
+        // directly or through a large cave.
+        let mut edges = vec!["start-c0".to_string(), "c199-end".to_string()];
+        for i in 0..199u8 {
+            edges.push(format!("c{}-c{}", i, i + 1));
+            if i % 2 == 0 {
+                let large = format!("L{}{}", (b'A' + i / 26) as char, (b'A' + i % 26) as char);
+                edges.push(format!("c{}-{}", i, large));
+                edges.push(format!("{}-c{}", large, i + 1));
+            }
+        }
+        let graph = parse(&edges.join(" "));
+        assert_eq!(count(&graph, "start", 0).unwrap(), 1 << 100);
+    }
+
+    #[test]
+    fn test_adjacent_large() {
+        let graph = parse("start-AB AB-CD CD-end CD-AB AB-e EF-EF");
+        let expected = Error::AdjacentLarge(vec![("AB".to_string(), "CD".to_string())]);
+        assert_eq!(check(&graph, "start"), Err(expected));
+        assert_eq!(
+            count(&graph, "start", 0).unwrap_err().to_string(),
+            "adjacent large caves AB-CD, the number of paths is infinite"
+        );
+        assert_eq!(check(&parse(LARGE), "start"), Ok(()));
+
+        // Large caves that no path goes through do not matter: EF cannot be
+        // reached, and GH and IJ only through start or end.
+        let graph = parse("start-a a-end EF-FG EF-ef start-GH GH-KL end-IJ IJ-MN");
+        assert_eq!(check(&graph, "start"), Ok(()));
+        assert_eq!(count(&graph, "start", 0), Ok(1));
+
+        // Neither does a large start cave, which is never revisited.
+        let graph = parse("ST-AB ST-a AB-a a-end");
+        assert_eq!(count(&graph, "ST", 0), Ok(2));
+        assert_eq!(count(&graph, "ST", 1), Ok(4));
+        let graph = parse("ST-AB AB-CD ST-end");
+        assert!(count(&graph, "ST", 0).is_ok());
+        let graph = parse("ST-AB AB-CD CD-end");
+        assert!(count(&graph, "ST", 0).is_err());
+    }
+
+    #[test]
+    fn test_dot() {
+        let graph = parse("start-A A-b A-BC b-end");
+        assert_eq!(
+            dot(&graph),
+            "graph caves {
+    \"A\" [shape=box, style=filled, fillcolor=lightgrey];
+    \"BC\" [shape=box, style=filled, fillcolor=lightgrey];
+    \"b\" [shape=ellipse];
+    \"end\" [shape=doublecircle];
+    \"start\" [shape=doublecircle];
+    \"A\" -- \"BC\" [color=red];
+    \"A\" -- \"b\";
+    \"A\" -- \"start\";
+    \"b\" -- \"end\";
+}
+"
+        );
+
+        // Every edge of the input appears exactly once.
+        let graph = parse(LARGE);
+        let dot = dot(&graph);
+        assert_eq!(
+            dot.matches(" -- ").count(),
+            LARGE.split_whitespace().count()
+        );
+    }
+
+    #[test]
+    fn test_options() {
+        let args = |s: &str| {
+            s.split_whitespace()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(Options::parse(&[]), Ok(Options::default()));
+        assert_eq!(
+            Options::parse(&args("--through A --twice --max-len 5 --through b")),
+            Ok(Options {
+                twice: true,
+                through: vec!["A".to_string(), "b".to_string()],
+                max_len: Some(5),
+            })
+        );
+        for bad in ["--max-len", "--max-len x", "--through", "--all"] {
+            assert_eq!(Options::parse(&args(bad)), Err(()), "{}", bad);
+        }
+    }
 }
@@ -1,10 +1,11 @@
 use advent_of_code_2021::util::lines;
 use std::collections::HashSet;
+use std::fmt;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 struct Dot(usize, usize);
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 enum Fold {
     H(usize),
     V(usize),
@@ -43,44 +44,169 @@ fn parse(lines: &[String]) -> (Vec<Dot>, Vec<Fold>) {
     (dots, folds)
 }
 
-fn fold(dots: &mut HashSet<Dot>, fold: &Fold) {
-    let moved: HashSet<Dot> = dots
-        .iter()
-        .cloned()
-        .filter(|dot| match fold {
-            Fold::H(x) => dot.0 > *x,
-            Fold::V(y) => dot.1 > *y,
-        })
-        .collect();
+impl Fold {
+    fn line(&self) -> usize {
+        match self {
+            Fold::H(x) => *x,
+            Fold::V(y) => *y,
+        }
+    }
 
-    for dot in moved.iter() {
-        dots.remove(dot);
+    /// The coordinate of the dot across the fold line.
+    fn across(&self, dot: &Dot) -> usize {
+        match self {
+            Fold::H(_) => dot.0,
+            Fold::V(_) => dot.1,
+        }
     }
 
-    moved
-        .into_iter()
-        .map(|dot| match fold {
-            Fold::H(x) => Dot(x - (dot.0 - x), dot.1),
-            Fold::V(y) => Dot(dot.0, y - (dot.1 - y)),
-        })
-        .for_each(|dot| {
-            dots.insert(dot);
+    fn with(&self, dot: &Dot, v: usize) -> Dot {
+        match self {
+            Fold::H(_) => Dot(v, dot.1),
+            Fold::V(_) => Dot(dot.0, v),
+        }
+    }
+}
+
+impl fmt::Display for Fold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fold::H(x) => write!(f, "x={}", x),
+            Fold::V(y) => write!(f, "y={}", y),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum Error {
+    /// A dot lies on the fold line.
+    OnLine(Dot),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::OnLine(dot) => write!(f, "dot {},{} lies on the fold line", dot.0, dot.1),
+        }
+    }
+}
+
+/// A fold as applied to the sheet, with what is needed to undo it.
+#[derive(Debug)]
+struct Step {
+    fold: Fold,
+    /// Size of the sheet across the fold line before folding.
+    size: usize,
+    /// How far the kept half moved: when the folded half is larger, the
+    /// result starts at its far edge instead of at the kept half's.
+    shift: usize,
+    /// Dots, after folding, that only came from the folded half.
+    moved: HashSet<Dot>,
+    /// Dots, after folding, where a dot from each half merged.
+    collisions: HashSet<Dot>,
+}
+
+/// Transparent paper that remembers how it was folded.
+struct Sheet {
+    dots: HashSet<Dot>,
+    width: usize,
+    height: usize,
+    history: Vec<Step>,
+}
+
+impl Sheet {
+    /// The smallest sheet that holds all dots.
+    fn new(dots: &[Dot]) -> Self {
+        Self {
+            dots: dots.iter().cloned().collect(),
+            width: dots.iter().map(|d| d.0 + 1).max().unwrap_or_default(),
+            height: dots.iter().map(|d| d.1 + 1).max().unwrap_or_default(),
+            history: Vec::new(),
+        }
+    }
+
+    fn size(&mut self, fold: &Fold) -> &mut usize {
+        match fold {
+            Fold::H(_) => &mut self.width,
+            Fold::V(_) => &mut self.height,
+        }
+    }
+
+    /// Folds the right or bottom half over the other one. The sheet then
+    /// spans the larger of both halves. The sheet may extend past the last
+    /// dot, so it grows to at least twice the fold line.
+    fn fold(&mut self, fold: Fold) -> Result<&Step, Error> {
+        let size = *self.size(&fold);
+        let line = fold.line();
+        if let Some(dot) = self
+            .dots
+            .iter()
+            .filter(|dot| fold.across(dot) == line)
+            .min_by_key(|dot| (dot.1, dot.0))
+        {
+            return Err(Error::OnLine(*dot));
+        }
+
+        let folded = line.max(size.max(2 * line + 1) - line - 1);
+        let shift = folded - line;
+        let mut kept = HashSet::new();
+        let mut moved = HashSet::new();
+        for dot in self.dots.iter() {
+            let v = fold.across(dot);
+            if v < line {
+                kept.insert(fold.with(dot, v + shift));
+            } else {
+                moved.insert(fold.with(dot, folded - (v - line)));
+            }
+        }
+
+        let collisions: HashSet<Dot> = kept.intersection(&moved).cloned().collect();
+        moved.retain(|dot| !kept.contains(dot));
+        self.dots = kept.union(&moved).cloned().collect();
+        *self.size(&fold) = folded;
+
+        self.history.push(Step {
+            fold,
+            size,
+            shift,
+            moved,
+            collisions,
         });
+        Ok(self.history.last().unwrap())
+    }
 
-    match fold {
-        Fold::H(x) => assert!(dots.iter().all(|dot| dot.0 < *x)),
-        Fold::V(y) => assert!(dots.iter().all(|dot| dot.1 < *y)),
-    };
+    /// Undoes the last fold, restoring the dots as they were before it.
+    fn unfold(&mut self) -> Option<Step> {
+        let step = self.history.pop()?;
+        let fold = step.fold;
+        let (line, folded) = (fold.line(), *self.size(&fold));
+
+        let mut dots = HashSet::new();
+        for dot in self.dots.iter() {
+            let v = fold.across(dot);
+            let moved = fold.with(dot, line + (folded - v));
+            if step.moved.contains(dot) {
+                dots.insert(moved);
+            } else {
+                dots.insert(fold.with(dot, v - step.shift));
+                if step.collisions.contains(dot) {
+                    dots.insert(moved);
+                }
+            }
+        }
+
+        self.dots = dots;
+        *self.size(&fold) = step.size;
+        Some(step)
+    }
 }
 
 fn print(dots: &HashSet<Dot>) -> Vec<Vec<char>> {
     let cols = dots.iter().map(|d| d.0).max().unwrap();
     let rows = dots.iter().map(|d| d.1).max().unwrap();
     (0..=rows)
-        .into_iter()
         .map(move |row| {
             (0..=cols)
-                .into_iter()
                 .map(move |col| {
                     if dots.contains(&Dot(col, row)) {
                         '#'
@@ -93,23 +219,323 @@ fn print(dots: &HashSet<Dot>) -> Vec<Vec<char>> {
         .collect()
 }
 
+/// Letters of the 4x6 block font used for answers, one string per row.
+const FONT: [(char, [&str; 6]); 17] = [
+    ('A', [".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+    ('B', ["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+    ('C', [".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+    ('E', ["####", "#...", "###.", "#...", "#...", "####"]),
+    ('F', ["####", "#...", "###.", "#...", "#...", "#..."]),
+    ('G', [".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+    ('H', ["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+    ('I', [".###", "..#.", "..#.", "..#.", "..#.", ".###"]),
+    ('J', ["..##", "...#", "...#", "...#", "#..#", ".##."]),
+    ('K', ["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+    ('L', ["#...", "#...", "#...", "#...", "#...", "####"]),
+    ('O', [".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('P', ["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+    ('R', ["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+    ('S', [".###", "#...", "#...", ".##.", "...#", "###."]),
+    ('U', ["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('Z', ["####", "...#", "..#.", ".#..", "#...", "####"]),
+];
+
+const WIDTH: usize = 4;
+const HEIGHT: usize = 6;
+/// Letters are drawn every five columns, starting at column 0.
+const PITCH: usize = WIDTH + 1;
+
+/// The 4x6 cell with its top left corner at `(x, y)`, one bit per pixel.
+fn glyph(dots: &HashSet<Dot>, x: usize, y: usize) -> u32 {
+    (0..HEIGHT)
+        .flat_map(|row| (0..WIDTH).map(move |col| (row, col)))
+        .fold(0, |bits, (row, col)| {
+            bits << 1 | dots.contains(&Dot(x + col, y + row)) as u32
+        })
+}
+
+fn bits(rows: &[&str; 6]) -> u32 {
+    rows.iter()
+        .flat_map(|row| row.bytes())
+        .fold(0, |bits, b| bits << 1 | (b == b'#') as u32)
+}
+
+/// Reads the letters spelled by the dots, with `?` for any cell that is not
+/// in the font. Cells are lined up on the pitch rather than on the leftmost
+/// dot, as a letter such as `I` leaves its first column empty.
+fn ocr(dots: &HashSet<Dot>) -> String {
+    let (Some(left), Some(top)) = (
+        dots.iter().map(|d| d.0).min(),
+        dots.iter().map(|d| d.1).min(),
+    ) else {
+        return String::new();
+    };
+    let right = dots.iter().map(|d| d.0).max().unwrap();
+    (left / PITCH..=right / PITCH)
+        .map(|i| {
+            let glyph = glyph(dots, i * PITCH, top);
+            FONT.iter()
+                .find(|(_, rows)| bits(rows) == glyph)
+                .map_or('?', |(c, _)| *c)
+        })
+        .collect()
+}
+
+/// Usage: day13 [history] < input
+///
+/// With `history`, prints the number of dots and collisions after each fold
+/// instead, then the number of dots after undoing each fold again.
 fn main() {
+    let history = std::env::args().nth(1).as_deref() == Some("history");
     let (dots, folds) = parse(&lines());
 
-    let f = folds[0];
-    let mut part1: HashSet<Dot> = dots.iter().cloned().collect();
-    fold(&mut part1, &f);
-    println!("{}", part1.len());
+    let mut sheet = Sheet::new(&dots);
+    for (i, f) in folds.into_iter().enumerate() {
+        let step = match sheet.fold(f) {
+            Ok(step) => step,
+            Err(e) => {
+                eprintln!("fold {}: {}", i + 1, e);
+                std::process::exit(1);
+            }
+        };
+        if history {
+            let collisions = step.collisions.len();
+            let (fold, dots) = (step.fold, sheet.dots.len());
+            println!("fold {}: {} dots, {} collisions", fold, dots, collisions);
+        } else if i == 0 {
+            println!("{}", sheet.dots.len());
+        }
+    }
+
+    if history {
+        while let Some(Step { fold, .. }) = sheet.unfold() {
+            println!("unfold {}: {} dots", fold, sheet.dots.len());
+        }
+        return;
+    }
+
+    let part2 = &sheet.dots;
+    let text = ocr(part2);
+    println!("{}", text);
+    if text.contains('?') {
+        for row in print(part2) {
+            for c in row {
+                print!("{}", c);
+            }
+            println!();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Dots drawn with `#` on a grid of text rows.
+    fn dots(art: &[&str]) -> HashSet<Dot> {
+        art.iter()
+            .enumerate()
+            .flat_map(|(y, row)| {
+                row.char_indices()
+                    .filter(|(_, c)| *c == '#')
+                    .map(move |(x, _)| Dot(x, y))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_ocr() {
+        let art = [
+            "#  # #### ###  #### #  #  ##  #  # ####",
+            "#  # #    #  #    # # #  #  # #  #    #",
+            "#  # ###  #  #   #  ##   #  # #  #   # ",
+            "#  # #    ###   #   # #  #### #  #  #  ",
+            "#  # #    # #  #    # #  #  # #  # #   ",
+            " ##  #    #  # #### #  # #  #  ##  ####",
+        ];
+        assert_eq!(ocr(&dots(&art)), "UFRZKAUZ");
+
+        // The whole font, starting a few letters and rows away from the origin.
+        let text: String = FONT.iter().map(|(c, _)| *c).collect();
+        let shifted: HashSet<Dot> = FONT
+            .iter()
+            .enumerate()
+            .flat_map(|(i, (_, rows))| {
+                dots(rows)
+                    .into_iter()
+                    .map(move |Dot(x, y)| Dot((3 + i) * PITCH + x, 2 + y))
+            })
+            .collect();
+        assert_eq!(ocr(&shifted), text);
+        assert_eq!(ocr(&HashSet::new()), "");
+    }
+
+    #[test]
+    fn test_ocr_first_column_empty() {
+        let art = [
+            " ###  ##  #  #",
+            "  #  #  # #  #",
+            "  #  #    ####",
+            "  #  #    #  #",
+            "  #  #  # #  #",
+            " ###  ##  #  #",
+        ];
+        assert_eq!(ocr(&dots(&art)), "ICH");
+    }
+
+    #[test]
+    fn test_unknown() {
+        let art = [
+            "#### #   #",
+            "#    ## ##",
+            "###  # # #",
+            "#    #   #",
+            "#    #   #",
+            "#### #   #",
+        ];
+        assert_eq!(ocr(&dots(&art)), "E?");
+    }
+
+    const EXAMPLE: &str = "6,10 0,14 9,10 0,3 10,4 4,11 6,0 6,12 4,1 0,13 10,12 3,4 3,0 8,4 \
+                           1,10 2,14 8,10 9,0";
+
+    fn example() -> Vec<Dot> {
+        EXAMPLE
+            .split_whitespace()
+            .map(|xy| {
+                let (x, y) = xy.split_once(',').unwrap();
+                Dot(x.parse().unwrap(), y.parse().unwrap())
+            })
+            .collect()
+    }
 
-    let mut part2: HashSet<Dot> = dots.iter().cloned().collect();
-    for f in folds {
-        fold(&mut part2, &f);
+    fn sorted(dots: &HashSet<Dot>) -> Vec<Dot> {
+        let mut dots: Vec<Dot> = dots.iter().cloned().collect();
+        dots.sort_by_key(|dot| (dot.1, dot.0));
+        dots
     }
 
-    for row in print(&part2) {
-        for c in row {
-            print!("{}", c);
+    #[test]
+    fn test_example() {
+        let dots = example();
+        let mut sheet = Sheet::new(&dots);
+        assert_eq!((sheet.width, sheet.height), (11, 15));
+
+        let step = sheet.fold(Fold::V(7)).unwrap();
+        assert_eq!(sorted(&step.collisions), vec![Dot(8, 4)]);
+        assert_eq!(sheet.dots.len(), 17);
+        sheet.fold(Fold::H(5)).unwrap();
+        assert_eq!(sheet.dots.len(), 16);
+        assert_eq!((sheet.width, sheet.height), (5, 7));
+        assert_eq!(print(&sheet.dots)[0], vec!['#'; 5]);
+
+        let collisions: Vec<usize> = sheet
+            .history
+            .iter()
+            .map(|step| step.collisions.len())
+            .collect();
+        assert_eq!(collisions, vec![1, 1]);
+
+        while sheet.unfold().is_some() {}
+        assert_eq!(sorted(&sheet.dots), sorted(&dots.into_iter().collect()));
+        assert_eq!((sheet.width, sheet.height), (11, 15));
+    }
+
+    #[test]
+    fn test_larger_half() {
+        let dots = vec![Dot(0, 0), Dot(3, 1), Dot(9, 2), Dot(1, 3), Dot(3, 3)];
+        let mut sheet = Sheet::new(&dots);
+        assert_eq!(sheet.width, 10);
+
+        // Folding at x=2 leaves 7 columns, and the left half lands on the
+        // right end of them.
+        let step = sheet.fold(Fold::H(2)).unwrap();
+        assert_eq!(step.shift, 5);
+        assert_eq!(sorted(&step.collisions), vec![Dot(6, 3)]);
+        assert_eq!(sheet.width, 7);
+        assert_eq!(
+            sorted(&sheet.dots),
+            vec![Dot(5, 0), Dot(6, 1), Dot(0, 2), Dot(6, 3)]
+        );
+
+        sheet.unfold().unwrap();
+        assert_eq!(sorted(&sheet.dots), sorted(&dots.into_iter().collect()));
+        assert_eq!(sheet.width, 10);
+        assert!(sheet.unfold().is_none());
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut sheet = Sheet::new(&example());
+        let e = sheet.fold(Fold::V(4)).unwrap_err();
+        assert_eq!(e, Error::OnLine(Dot(3, 4)));
+        assert_eq!(e.to_string(), "dot 3,4 lies on the fold line");
+        assert_eq!(Fold::V(4).to_string(), "y=4");
+        assert!(sheet.history.is_empty());
+    }
+
+    #[test]
+    fn test_past_edge() {
+        // The sheet extends past the last dot, up to twice the fold line.
+        let dots = vec![Dot(0, 0), Dot(1, 1), Dot(0, 14)];
+        let mut sheet = Sheet::new(&dots);
+        assert_eq!(sheet.width, 2);
+        let step = sheet.fold(Fold::H(5)).unwrap();
+        assert_eq!(step.shift, 0);
+        assert_eq!(sheet.width, 5);
+        assert_eq!(sheet.dots.len(), 3);
+
+        sheet.fold(Fold::V(7)).unwrap();
+        assert_eq!(sorted(&sheet.dots), vec![Dot(0, 0), Dot(1, 1)]);
+
+        while sheet.unfold().is_some() {}
+        assert_eq!(sorted(&sheet.dots), sorted(&dots.into_iter().collect()));
+        assert_eq!((sheet.width, sheet.height), (2, 15));
+    }
+
+    #[test]
+    fn test_unfold() {
+        // Folds on either side of the centre; those through a dot fail and
+        // leave the sheet as it was.
+        let cases = [
+            (EXAMPLE, "x=5 y=7 y=2 x=1 y=1"),
+            (EXAMPLE, "x=2 y=9 x=5 y=2 x=0"),
+            ("0,0 9,0 2,3 7,3 4,5 1,1 8,9", "x=2 x=3 y=2 x=1 y=0 x=2"),
+            ("5,5 0,12 12,0 3,8 8,3 6,6 11,11", "y=4 x=9 y=1 x=5 x=2 y=2"),
+            ("1,0 0,1 3,0 0,3", "x=1 x=2 y=2 y=1"),
+        ];
+        let mut folded = 0;
+        for (dots, folds) in cases {
+            let dots: Vec<Dot> = dots
+                .split_whitespace()
+                .map(|xy| {
+                    let (x, y) = xy.split_once(',').unwrap();
+                    Dot(x.parse().unwrap(), y.parse().unwrap())
+                })
+                .collect();
+            let mut sheet = Sheet::new(&dots);
+            let mut sizes = vec![];
+            for fold in folds.split_whitespace() {
+                let (axis, line) = fold.split_once('=').unwrap();
+                let line = line.parse().unwrap();
+                let fold = if axis == "x" {
+                    Fold::H(line)
+                } else {
+                    Fold::V(line)
+                };
+                let before = sheet.dots.len();
+                if let Ok(step) = sheet.fold(fold) {
+                    let merged = step.collisions.len();
+                    assert_eq!(sheet.dots.len(), before - merged);
+                    sizes.push(before);
+                }
+            }
+            folded += sizes.len();
+            while sheet.unfold().is_some() {
+                assert_eq!(sheet.dots.len(), sizes.pop().unwrap());
+            }
+            assert_eq!(sorted(&sheet.dots), sorted(&dots.iter().cloned().collect()));
         }
-        println!();
+        assert_eq!(folded, 13);
     }
 }